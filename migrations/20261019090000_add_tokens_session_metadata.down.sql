@@ -0,0 +1,5 @@
+-- Add down migration script here
+alter table tokens drop column if exists user_agent;
+alter table tokens drop column if exists ip;
+alter table tokens drop column if exists last_used_at;
+alter table tokens drop column if exists created_at;
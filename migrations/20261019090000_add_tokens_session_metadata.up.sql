@@ -0,0 +1,5 @@
+-- Add up migration script here
+alter table tokens add column created_at timestamp(0) with time zone NOT NULL DEFAULT NOW();
+alter table tokens add column last_used_at timestamp(0) with time zone;
+alter table tokens add column ip text;
+alter table tokens add column user_agent text;
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS tokens_family_idx;
+alter table tokens drop column if exists consumed;
+alter table tokens drop column if exists family;
@@ -0,0 +1,4 @@
+-- Add up migration script here
+alter table tokens add column family bytea;
+alter table tokens add column consumed bool NOT NULL DEFAULT false;
+CREATE INDEX IF NOT EXISTS tokens_family_idx ON tokens (family);
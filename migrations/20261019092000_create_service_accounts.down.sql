@@ -0,0 +1,4 @@
+-- Add down migration script here
+DELETE FROM permissions WHERE code = 'service-accounts:admin';
+DROP TABLE IF EXISTS api_keys;
+DROP TABLE IF EXISTS service_accounts;
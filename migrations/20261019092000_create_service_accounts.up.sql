@@ -0,0 +1,20 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS service_accounts (
+    id bigserial PRIMARY KEY,
+    created_at timestamp(0) with time zone NOT NULL DEFAULT NOW(),
+    name text UNIQUE NOT NULL,
+    created_by bigint REFERENCES users ON DELETE SET NULL
+);
+
+CREATE TABLE IF NOT EXISTS api_keys (
+    id bigserial PRIMARY KEY,
+    service_account_id bigint NOT NULL REFERENCES service_accounts ON DELETE CASCADE,
+    hash bytea UNIQUE NOT NULL,
+    name text NOT NULL,
+    permissions text[] NOT NULL,
+    expiry timestamp(0) with time zone,
+    created_at timestamp(0) with time zone NOT NULL DEFAULT NOW(),
+    last_used_at timestamp(0) with time zone
+);
+
+INSERT INTO permissions (code) VALUES ('service-accounts:admin');
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS recovery_codes;
+DROP TABLE IF EXISTS totp_secrets;
@@ -0,0 +1,2 @@
+-- Add up migration script here
+INSERT INTO permissions (code) VALUES ('users:admin');
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS users_roles;
+DROP TABLE IF EXISTS roles;
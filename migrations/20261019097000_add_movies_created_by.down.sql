@@ -0,0 +1,17 @@
+-- Add down migration script here
+INSERT INTO permissions (code) VALUES ('movies:write');
+
+INSERT INTO users_permissions
+SELECT users_permissions.user_id, (SELECT id FROM permissions WHERE code = 'movies:write')
+FROM users_permissions
+INNER JOIN permissions ON permissions.id = users_permissions.permission_id
+WHERE permissions.code IN ('movies:write:own', 'movies:write:any')
+ON CONFLICT DO NOTHING;
+
+UPDATE roles SET permissions = array_replace(array_replace(permissions, 'movies:write:any', 'movies:write'), 'movies:write:own', 'movies:write');
+UPDATE api_keys SET permissions = array_replace(array_replace(permissions, 'movies:write:any', 'movies:write'), 'movies:write:own', 'movies:write');
+
+DELETE FROM permissions WHERE code IN ('movies:write:own', 'movies:write:any');
+
+DROP INDEX IF EXISTS movies_created_by_idx;
+ALTER TABLE movies DROP COLUMN IF EXISTS created_by;
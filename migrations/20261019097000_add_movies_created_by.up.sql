@@ -0,0 +1,17 @@
+-- Add up migration script here
+ALTER TABLE movies ADD COLUMN IF NOT EXISTS created_by bigint REFERENCES users ON DELETE SET NULL;
+CREATE INDEX IF NOT EXISTS movies_created_by_idx ON movies (created_by);
+
+INSERT INTO permissions (code) VALUES ('movies:write:own'), ('movies:write:any');
+
+-- 'movies:write' allowed editing every movie, so its holders keep that through 'movies:write:any'
+INSERT INTO users_permissions
+SELECT users_permissions.user_id, (SELECT id FROM permissions WHERE code = 'movies:write:any')
+FROM users_permissions
+INNER JOIN permissions ON permissions.id = users_permissions.permission_id
+WHERE permissions.code = 'movies:write';
+
+UPDATE roles SET permissions = array_replace(permissions, 'movies:write', 'movies:write:any');
+UPDATE api_keys SET permissions = array_replace(permissions, 'movies:write', 'movies:write:any');
+
+DELETE FROM permissions WHERE code = 'movies:write';
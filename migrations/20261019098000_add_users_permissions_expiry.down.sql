@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS users_permissions_expires_at_idx;
+ALTER TABLE users_permissions DROP COLUMN IF EXISTS granted_by;
+ALTER TABLE users_permissions DROP COLUMN IF EXISTS expires_at;
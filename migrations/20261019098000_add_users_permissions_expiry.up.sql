@@ -0,0 +1,5 @@
+-- Add up migration script here
+ALTER TABLE users_permissions ADD COLUMN IF NOT EXISTS expires_at timestamp(0) with time zone;
+ALTER TABLE users_permissions ADD COLUMN IF NOT EXISTS granted_by bigint REFERENCES users ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS users_permissions_expires_at_idx ON users_permissions (expires_at) WHERE expires_at IS NOT NULL;
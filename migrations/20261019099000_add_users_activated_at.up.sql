@@ -0,0 +1,13 @@
+-- Add up migration script here
+ALTER TABLE users ADD COLUMN IF NOT EXISTS activated_at timestamp(0) with time zone;
+
+-- activating consumes the activation token, so an inactive account without
+-- one was activated once and switched off later rather than never activated
+UPDATE users SET activated_at = created_at
+WHERE activated_at IS NULL
+AND (activated OR NOT EXISTS (
+    SELECT 1 FROM tokens WHERE tokens.user_id = users.id AND tokens.scope = 'activation'
+));
+
+CREATE INDEX IF NOT EXISTS users_never_activated_idx ON users (created_at) WHERE activated_at IS NULL;
+CREATE INDEX IF NOT EXISTS tokens_expiry_idx ON tokens (expiry);
@@ -0,0 +1,12 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS invitations (
+    id bigserial PRIMARY KEY,
+    created_at timestamp(0) with time zone NOT NULL DEFAULT NOW(),
+    hash bytea UNIQUE NOT NULL,
+    email citext NOT NULL,
+    permissions text[] NOT NULL,
+    expiry timestamp(0) with time zone NOT NULL,
+    invited_by bigint REFERENCES users ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS invitations_expiry_idx ON invitations (expiry);
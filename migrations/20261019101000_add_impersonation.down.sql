@@ -0,0 +1,5 @@
+-- Add down migration script here
+DELETE FROM tokens WHERE impersonator_id IS NOT NULL;
+ALTER TABLE tokens DROP COLUMN IF EXISTS impersonator_id;
+
+DELETE FROM permissions WHERE code = 'users:impersonate';
@@ -0,0 +1,4 @@
+-- Add up migration script here
+INSERT INTO permissions (code) VALUES ('users:impersonate');
+
+ALTER TABLE tokens ADD COLUMN IF NOT EXISTS impersonator_id bigint REFERENCES users ON DELETE CASCADE;
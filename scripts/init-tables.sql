@@ -25,16 +25,33 @@ CREATE TABLE IF NOT EXISTS users (
     email citext UNIQUE NOT NULL,
     password_hash text NOT NULL,
     activated bool NOT NULL,
-    version integer NOT NULL DEFAULT 1
+    version integer NOT NULL DEFAULT 1,
+    activated_at timestamp(0) with time zone,
+    locale text NOT NULL DEFAULT 'en'
 );
 
+CREATE INDEX IF NOT EXISTS users_never_activated_idx ON users (created_at) WHERE activated_at IS NULL;
+
+ALTER TABLE movies ADD COLUMN IF NOT EXISTS created_by bigint REFERENCES users ON DELETE SET NULL;
+CREATE INDEX IF NOT EXISTS movies_created_by_idx ON movies (created_by);
+
 CREATE TABLE IF NOT EXISTS tokens (
     hash bytea PRIMARY KEY,
     user_id bigint NOT NULL REFERENCES users ON DELETE CASCADE,
     expiry timestamp(0) with time zone NOT NULL,
-    scope text NOT NULL
+    scope text NOT NULL,
+    created_at timestamp(0) with time zone NOT NULL DEFAULT NOW(),
+    last_used_at timestamp(0) with time zone,
+    ip text,
+    user_agent text,
+    family bytea,
+    consumed bool NOT NULL DEFAULT false,
+    impersonator_id bigint REFERENCES users ON DELETE CASCADE
 );
 
+CREATE INDEX IF NOT EXISTS tokens_family_idx ON tokens (family);
+CREATE INDEX IF NOT EXISTS tokens_expiry_idx ON tokens (expiry);
+
 CREATE TABLE IF NOT EXISTS permissions (
     id bigserial PRIMARY KEY,
     code text NOT NULL
@@ -43,23 +60,128 @@ CREATE TABLE IF NOT EXISTS permissions (
 CREATE TABLE IF NOT EXISTS users_permissions (
     user_id bigint NOT NULL REFERENCES users ON DELETE CASCADE,
     permission_id bigint NOT NULL REFERENCES permissions ON DELETE CASCADE,
+    expires_at timestamp(0) with time zone,
+    granted_by bigint REFERENCES users ON DELETE SET NULL,
     PRIMARY KEY (user_id, permission_id)
 );
 
--- add the two permissions to the table
-INSERT INTO permissions (code) VALUES ('movies:read'), ('movies:write');
+CREATE INDEX IF NOT EXISTS users_permissions_expires_at_idx ON users_permissions (expires_at) WHERE expires_at IS NOT NULL;
+
+-- add the permissions to the table
+INSERT INTO permissions (code) VALUES ('movies:read'), ('movies:write:own'), ('movies:write:any'), ('service-accounts:admin'), ('users:admin'), ('users:impersonate');
+
+CREATE TABLE IF NOT EXISTS service_accounts (
+    id bigserial PRIMARY KEY,
+    created_at timestamp(0) with time zone NOT NULL DEFAULT NOW(),
+    name text UNIQUE NOT NULL,
+    created_by bigint REFERENCES users ON DELETE SET NULL
+);
+
+CREATE TABLE IF NOT EXISTS api_keys (
+    id bigserial PRIMARY KEY,
+    service_account_id bigint NOT NULL REFERENCES service_accounts ON DELETE CASCADE,
+    hash bytea UNIQUE NOT NULL,
+    name text NOT NULL,
+    permissions text[] NOT NULL,
+    expiry timestamp(0) with time zone,
+    created_at timestamp(0) with time zone NOT NULL DEFAULT NOW(),
+    last_used_at timestamp(0) with time zone
+);
+
+CREATE TABLE IF NOT EXISTS totp_secrets (
+    user_id bigint PRIMARY KEY REFERENCES users ON DELETE CASCADE,
+    created_at timestamp(0) with time zone NOT NULL DEFAULT NOW(),
+    secret bytea NOT NULL,
+    enabled bool NOT NULL DEFAULT false,
+    last_step bigint
+);
+
+CREATE TABLE IF NOT EXISTS recovery_codes (
+    id bigserial PRIMARY KEY,
+    user_id bigint NOT NULL REFERENCES users ON DELETE CASCADE,
+    code_hash text NOT NULL,
+    used bool NOT NULL DEFAULT false
+);
+
+CREATE TABLE IF NOT EXISTS user_identities (
+    provider text NOT NULL,
+    subject text NOT NULL,
+    user_id bigint NOT NULL REFERENCES users ON DELETE CASCADE,
+    created_at timestamp(0) with time zone NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (provider, subject)
+);
+
+CREATE TABLE IF NOT EXISTS roles (
+    id bigserial PRIMARY KEY,
+    created_at timestamp(0) with time zone NOT NULL DEFAULT NOW(),
+    name text UNIQUE NOT NULL,
+    permissions text[] NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS users_roles (
+    user_id bigint NOT NULL REFERENCES users ON DELETE CASCADE,
+    role_id bigint NOT NULL REFERENCES roles ON DELETE CASCADE,
+    PRIMARY KEY (user_id, role_id)
+);
+
+CREATE TABLE IF NOT EXISTS invitations (
+    id bigserial PRIMARY KEY,
+    created_at timestamp(0) with time zone NOT NULL DEFAULT NOW(),
+    hash bytea UNIQUE NOT NULL,
+    email citext NOT NULL,
+    permissions text[] NOT NULL,
+    expiry timestamp(0) with time zone NOT NULL,
+    invited_by bigint REFERENCES users ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS invitations_expiry_idx ON invitations (expiry);
+
+CREATE TABLE IF NOT EXISTS password_history (
+    id bigserial PRIMARY KEY,
+    user_id bigint NOT NULL REFERENCES users ON DELETE CASCADE,
+    password_hash text NOT NULL,
+    created_at timestamp(0) with time zone NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS password_history_user_idx ON password_history (user_id, id DESC);
+
+INSERT INTO roles (name, permissions) VALUES
+('viewer', '{"movies:read"}'),
+('editor', '{"movies:*"}'),
+('admin', '{"*"}');
 
 -- seed user alice and bob
-insert into users (name, email, password_hash, activated) values 
-('alice', 'alice@example.com', '$argon2id$v=19$m=15000,t=2,p=1$cB5dpwlRXNmG4gZ3Wd0brQ$XE1vZSzgGs1lJeWt7ha3C+3ujyBDh/cbnJtkP0hbMn8',  true),
-('bob', 'bob@example.com', '$argon2id$v=19$m=15000,t=2,p=1$fjJQedxhqZQ2gYK90Qraeg$BiAA5bHU+dSHIDJpNagF1kwPcKjjTznUmVkyzouviRU', true);
+insert into users (name, email, password_hash, activated, activated_at) values 
+('alice', 'alice@example.com', '$argon2id$v=19$m=15000,t=2,p=1$cB5dpwlRXNmG4gZ3Wd0brQ$XE1vZSzgGs1lJeWt7ha3C+3ujyBDh/cbnJtkP0hbMn8',  true, now()),
+('bob', 'bob@example.com', '$argon2id$v=19$m=15000,t=2,p=1$fjJQedxhqZQ2gYK90Qraeg$BiAA5bHU+dSHIDJpNagF1kwPcKjjTznUmVkyzouviRU', true, now());
 
 -- give alice and bob 'movies:read' permission
 insert into users_permissions select id, (select id from permissions where code = 'movies:read') from users;
 
--- give alice 'movies:write' permission
+-- give alice 'movies:write:any' permission
+insert into users_permissions
+values (
+	(select id from users where email = 'alice@example.com'),
+	(select id from permissions where code = 'movies:write:any')
+);
+
+-- give alice 'service-accounts:admin' permission
+insert into users_permissions
+values (
+	(select id from users where email = 'alice@example.com'),
+	(select id from permissions where code = 'service-accounts:admin')
+);
+
+-- give alice 'users:admin' permission
+insert into users_permissions
+values (
+	(select id from users where email = 'alice@example.com'),
+	(select id from permissions where code = 'users:admin')
+);
+
+-- give alice 'users:impersonate' permission
 insert into users_permissions
 values (
 	(select id from users where email = 'alice@example.com'),
-	(select id from permissions where code = 'movies:write')
+	(select id from permissions where code = 'users:impersonate')
 );
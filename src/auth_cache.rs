@@ -0,0 +1,264 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use data_encoding::HEXLOWER;
+use redis::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::token::TokenAuth;
+use crate::Error;
+
+// generation counters only need to outlive the entries written under them
+const GENERATION_TTL_SECS: u64 = 24 * 60 * 60;
+
+// bumped along with every user generation, see `AuthCache::epoch`
+const EPOCH_KEY: &str = "auth_cache:epoch";
+
+// Caches what an opaque access token resolves to so that protected requests
+// skip the database lookup entirely. Every entry carries the
+// generation of its user, bumping the generation drops all of that user's
+// entries at once without having to know which tokens they hold.
+#[derive(Debug, Clone)]
+pub struct AuthCache {
+    redis: Client,
+    ttl_secs: u64,
+    stats: Arc<Stats>,
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    generation: u64,
+    auth: TokenAuth,
+}
+
+impl AuthCache {
+    pub fn new(redis: Client, ttl: Duration) -> Self {
+        Self {
+            redis,
+            ttl_secs: ttl.as_secs(),
+            stats: Arc::default(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.ttl_secs > 0
+    }
+
+    // redis failures are logged and treated as a miss, the database stays the source of truth
+    pub async fn get(&self, token_hash: &[u8]) -> Option<TokenAuth> {
+        if !self.enabled() {
+            return None;
+        }
+
+        let auth = match self.lookup(token_hash).await {
+            Ok(auth) => auth,
+            Err(e) => {
+                tracing::error!(err = %e, "auth cache lookup failed");
+                None
+            }
+        };
+
+        match auth {
+            Some(_) => self.stats.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.stats.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        tracing::debug!(hit = auth.is_some(), "auth cache lookup");
+
+        auth
+    }
+
+    // Read before the database lookup and handed to `put`. The user is only known once
+    // the lookup is done, so this counts invalidations of any user: an entry is
+    // only written if nothing was invalidated while it was being looked up.
+    pub async fn epoch(&self) -> Option<u64> {
+        if !self.enabled() {
+            return None;
+        }
+
+        let ret = async {
+            let mut conn = self.conn().await?;
+            redis::cmd("GET")
+                .arg(EPOCH_KEY)
+                .query_async::<_, Option<u64>>(&mut conn)
+                .await
+                .context("failed to read auth cache epoch")
+                .map_err(Error::UnexpectedError)
+        };
+
+        match ret.await {
+            Ok(epoch) => Some(epoch.unwrap_or(0)),
+            Err(e) => {
+                tracing::error!(err = %e, "failed to read auth cache epoch");
+                None
+            }
+        }
+    }
+
+    pub async fn put(&self, token_hash: &[u8], auth: TokenAuth, epoch: Option<u64>) {
+        let Some(epoch) = epoch else {
+            return;
+        };
+
+        // never keep an entry past the token's own expiry
+        let secs_left = (auth.expiry - Utc::now()).num_seconds();
+        let ttl = self.ttl_secs.min(secs_left.max(0) as u64);
+        if ttl == 0 {
+            return;
+        }
+
+        let ret = async {
+            let mut conn = self.conn().await?;
+            // read together so a bump after this point leaves the entry behind the generation
+            let (current, generation): (Option<u64>, Option<u64>) = redis::pipe()
+                .atomic()
+                .cmd("GET").arg(EPOCH_KEY)
+                .cmd("GET").arg(generation_key(auth.user_id))
+                .query_async(&mut conn)
+                .await
+                .context("failed to read auth cache generation")
+                .map_err(Error::UnexpectedError)?;
+            if current.unwrap_or(0) != epoch {
+                tracing::debug!("auth cache invalidated during lookup, entry not written");
+                return Ok(());
+            }
+
+            let value = serde_json::to_string(&Entry { generation: generation.unwrap_or(0), auth })
+                .context("failed to encode auth cache entry")
+                .map_err(Error::UnexpectedError)?;
+            redis::cmd("SET")
+                .arg(token_key(token_hash))
+                .arg(value)
+                .arg("EX")
+                .arg(ttl)
+                .query_async::<_, ()>(&mut conn)
+                .await
+                .context("failed to write auth cache entry")
+                .map_err(Error::UnexpectedError)
+        };
+
+        if let Err(e) = ret.await {
+            tracing::error!(err = %e, "failed to fill auth cache");
+        }
+    }
+
+    // a failed invalidation can only leave entries around for one ttl, which is why it is short
+    pub async fn invalidate_user(&self, user_id: i64) {
+        if !self.enabled() {
+            return;
+        }
+
+        let ret = async {
+            let mut conn = self.conn().await?;
+            redis::pipe()
+                .atomic()
+                .cmd("INCR").arg(generation_key(user_id)).ignore()
+                .cmd("EXPIRE").arg(generation_key(user_id)).arg(GENERATION_TTL_SECS).ignore()
+                .cmd("INCR").arg(EPOCH_KEY).ignore()
+                .query_async::<_, ()>(&mut conn)
+                .await
+                .context("failed to bump auth cache generation")
+                .map_err(Error::UnexpectedError)
+        };
+
+        match ret.await {
+            Ok(()) => tracing::debug!(user_id, "auth cache invalidated"),
+            Err(e) => tracing::error!(err = %e, user_id, "failed to invalidate auth cache"),
+        }
+    }
+
+    // returns the hits and misses counted since the previous call
+    pub fn take_stats(&self) -> (u64, u64) {
+        (
+            self.stats.hits.swap(0, Ordering::Relaxed),
+            self.stats.misses.swap(0, Ordering::Relaxed),
+        )
+    }
+
+    async fn lookup(&self, token_hash: &[u8]) -> Result<Option<TokenAuth>, Error> {
+        let mut conn = self.conn().await?;
+        let value: Option<String> = redis::cmd("GET")
+            .arg(token_key(token_hash))
+            .query_async(&mut conn)
+            .await
+            .context("failed to read auth cache entry")
+            .map_err(Error::UnexpectedError)?;
+        let Some(value) = value else {
+            return Ok(None);
+        };
+
+        let entry: Entry = serde_json::from_str(&value)
+            .context("failed to decode auth cache entry")
+            .map_err(Error::UnexpectedError)?;
+
+        let generation: Option<u64> = redis::cmd("GET")
+            .arg(generation_key(entry.auth.user_id))
+            .query_async(&mut conn)
+            .await
+            .context("failed to read auth cache generation")
+            .map_err(Error::UnexpectedError)?;
+
+        Ok(is_fresh(&entry, generation.unwrap_or(0), Utc::now()).then_some(entry.auth))
+    }
+
+    async fn conn(&self) -> Result<redis::aio::Connection, Error> {
+        self.redis
+            .get_async_connection()
+            .await
+            .context("failed to get redis conn in auth cache")
+            .map_err(Error::UnexpectedError)
+    }
+}
+
+fn is_fresh(entry: &Entry, generation: u64, now: DateTime<Utc>) -> bool {
+    entry.generation == generation && entry.auth.expiry > now
+}
+
+fn token_key(token_hash: &[u8]) -> String {
+    format!("auth_cache:token:{}", HEXLOWER.encode(token_hash))
+}
+
+fn generation_key(user_id: i64) -> String {
+    format!("auth_cache:gen:{}", user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_fresh, Entry};
+    use crate::token::TokenAuth;
+    use chrono::{Duration, Utc};
+
+    fn entry(generation: u64) -> Entry {
+        Entry {
+            generation,
+            auth: TokenAuth {
+                user_id: 1,
+                activated: true,
+                impersonator_id: None,
+                permissions: vec!["movies:read".to_owned()],
+                roles: vec![],
+                expiry: Utc::now() + Duration::hours(1),
+            },
+        }
+    }
+
+    #[test]
+    fn bumped_generation_drops_the_entry() {
+        let now = Utc::now();
+        assert!(is_fresh(&entry(2), 2, now));
+        assert!(!is_fresh(&entry(2), 3, now));
+    }
+
+    #[test]
+    fn expired_token_is_never_served() {
+        assert!(!is_fresh(&entry(0), 0, Utc::now() + Duration::hours(2)));
+    }
+}
@@ -0,0 +1,101 @@
+use anyhow::Context;
+use data_encoding::HEXUPPER;
+use memmap2::Mmap;
+use sha1::{Digest, Sha1};
+use std::fs::File;
+use std::sync::Arc;
+
+use crate::Error;
+
+const HASH_LEN: usize = 40;
+
+// The Have I Been Pwned "ordered by hash" download: one `<SHA-1 hex>:<count>`
+// per line, sorted by hash. The file is memory mapped and binary searched in
+// place, so a lookup touches a few pages instead of loading the whole corpus.
+#[derive(Clone)]
+pub struct BreachList {
+    map: Arc<Mmap>,
+}
+
+impl std::fmt::Debug for BreachList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BreachList").field("len", &self.map.len()).finish()
+    }
+}
+
+impl BreachList {
+    pub fn open(path: &str) -> Result<Self, Error> {
+        let file = File::open(path)
+            .with_context(|| format!("failed to open breached password file {}", path))
+            .map_err(Error::UnexpectedError)?;
+        // safety: the file is treated as read only, it must not be truncated while the server runs
+        let map = unsafe { Mmap::map(&file) }
+            .with_context(|| format!("failed to map breached password file {}", path))
+            .map_err(Error::UnexpectedError)?;
+
+        Ok(Self { map: Arc::new(map) })
+    }
+
+    pub fn contains(&self, password: &str) -> bool {
+        let hash = HEXUPPER.encode(&Sha1::digest(password.as_bytes()));
+        search(&self.map, hash.as_bytes())
+    }
+}
+
+fn search(data: &[u8], hash: &[u8]) -> bool {
+    // lo always sits on a line start, hi on a line start or the end of the data
+    let (mut lo, mut hi) = (0, data.len());
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        let start = data[lo..mid]
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(lo, |p| lo + p + 1);
+        let end = data[start..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(data.len(), |p| start + p + 1);
+
+        let line = &data[start..end];
+        let line_hash = &line[..HASH_LEN.min(line.len())];
+        match line_hash.cmp(hash) {
+            std::cmp::Ordering::Equal => return true,
+            std::cmp::Ordering::Less => lo = end,
+            std::cmp::Ordering::Greater => hi = start,
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::search;
+    use data_encoding::HEXUPPER;
+    use sha1::{Digest, Sha1};
+
+    fn hash(s: &str) -> String {
+        HEXUPPER.encode(&Sha1::digest(s.as_bytes()))
+    }
+
+    fn corpus(passwords: &[&str]) -> Vec<u8> {
+        let mut hashes: Vec<String> = passwords.iter().map(|p| hash(p)).collect();
+        hashes.sort();
+        hashes.iter().enumerate().map(|(i, h)| format!("{}:{}\r\n", h, i + 1)).collect::<String>().into_bytes()
+    }
+
+    #[test]
+    fn every_listed_password_is_found() {
+        let passwords = ["password", "123456", "qwerty", "letmein", "dragon", "monkey", "trustno1"];
+        let data = corpus(&passwords);
+        for p in passwords {
+            assert!(search(&data, hash(p).as_bytes()), "{} not found", p);
+        }
+    }
+
+    #[test]
+    fn unlisted_password_is_not_found() {
+        let data = corpus(&["password", "123456", "qwerty"]);
+        assert!(!search(&data, hash("|correcthorsebattery$staple").as_bytes()));
+        assert!(!search(&[], hash("password").as_bytes()));
+    }
+}
@@ -1,6 +1,6 @@
 use std::time::Duration;
 use std::num::ParseIntError;
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 
 use crate::errors::Error;
 
@@ -24,6 +24,18 @@ pub struct Config {
 
     #[command(flatten)]
     pub mail: MailConfig,
+
+    #[command(flatten)]
+    pub auth: AuthConfig,
+
+    #[command(flatten)]
+    pub rate_limit: RateLimitConfig,
+
+    #[command(flatten)]
+    pub oidc: OidcConfig,
+
+    #[command(flatten)]
+    pub sweep: SweepConfig,
 }
 
 #[derive(clap::Args, PartialEq, Debug)]
@@ -57,6 +69,244 @@ pub struct MailConfig {
     pub mail_password: String,
 }
 
+#[derive(clap::Args, PartialEq, Debug, Clone)]
+pub struct AuthConfig {
+    #[arg(value_parser = |arg: &str| -> Result<Duration, ParseIntError> {Ok(Duration::from_secs(arg.parse()?))})]
+    #[clap(long, default_value = "86400")]
+    pub access_token_ttl: Duration,
+
+    #[arg(value_parser = |arg: &str| -> Result<Duration, ParseIntError> {Ok(Duration::from_secs(arg.parse()?))})]
+    #[clap(long, default_value = "2592000")]
+    pub refresh_token_ttl: Duration,
+
+    #[clap(long, value_enum, default_value = "opaque")]
+    pub auth_mode: AuthMode,
+
+    /// <kid>:hs256:<secret> or <kid>:eddsa:<private pem>:<public pem>, the first one signs
+    #[clap(long = "jwt-key")]
+    pub jwt_keys: Vec<JwtKeySpec>,
+
+    #[arg(value_parser = |arg: &str| -> Result<Duration, ParseIntError> {Ok(Duration::from_secs(arg.parse()?))})]
+    #[clap(long, default_value = "900")]
+    pub jwt_token_ttl: Duration,
+
+    /// failed logins after which an account is locked out
+    #[clap(long, default_value = "10")]
+    pub login_max_attempts: u32,
+
+    /// failed logins after which a client ip is locked out
+    #[clap(long, default_value = "100")]
+    pub login_ip_max_attempts: u32,
+
+    #[arg(value_parser = |arg: &str| -> Result<Duration, ParseIntError> {Ok(Duration::from_secs(arg.parse()?))})]
+    #[clap(long, default_value = "900")]
+    pub login_lockout: Duration,
+
+    /// argon2 memory cost in KiB
+    #[clap(long, default_value = "15000")]
+    pub argon2_memory: u32,
+
+    /// argon2 number of passes
+    #[clap(long, default_value = "2")]
+    pub argon2_iterations: u32,
+
+    /// argon2 degree of parallelism
+    #[clap(long, default_value = "1")]
+    pub argon2_parallelism: u32,
+
+    /// sorted SHA-1 hash list in the Have I Been Pwned format, new passwords found in it are rejected
+    #[clap(long)]
+    pub breached_password_file: Option<String>,
+
+    /// role given to new accounts
+    #[clap(long, default_value = "viewer")]
+    pub default_role: String,
+
+    /// JSON authorization rules, added to the built in ones unless the file sets include_defaults to false
+    #[clap(long)]
+    pub policy_file: Option<String>,
+
+    /// seconds an access token's user and permissions are cached in redis, 0 disables the cache
+    #[arg(value_parser = |arg: &str| -> Result<Duration, ParseIntError> {Ok(Duration::from_secs(arg.parse()?))})]
+    #[clap(long, default_value = "30")]
+    pub auth_cache_ttl: Duration,
+
+    /// who may open an account, invitations are accepted under every policy but closed
+    #[clap(long, value_enum, default_value = "open")]
+    pub registration_policy: RegistrationPolicy,
+
+    /// email domain accepted by the domain_allowlist registration policy, may be repeated
+    #[clap(long = "allowed-email-domain")]
+    pub allowed_email_domains: Vec<String>,
+
+    /// file listing disposable email domains that may not register, one per line
+    #[clap(long)]
+    pub disposable_domains_file: Option<String>,
+
+    /// seconds an impersonation token stays valid
+    #[arg(value_parser = |arg: &str| -> Result<Duration, ParseIntError> {Ok(Duration::from_secs(arg.parse()?))})]
+    #[clap(long, default_value = "900")]
+    pub impersonation_ttl: Duration,
+
+    /// answer login, activation, password reset and sign in link requests the same way whether or not the email has an account
+    #[clap(long)]
+    pub enumeration_protection: bool,
+
+    /// previous passwords a user may not reuse besides the current one, 0 allows reusing any
+    #[clap(long, default_value = "5")]
+    pub password_history: u32,
+}
+
+// the config gets logged at startup, so hs256 secrets must never show up in Debug
+#[derive(PartialEq, Clone)]
+pub struct JwtKeySpec(String);
+
+impl From<String> for JwtKeySpec {
+    fn from(spec: String) -> Self {
+        Self(spec)
+    }
+}
+
+impl std::ops::Deref for JwtKeySpec {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for JwtKeySpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0.splitn(3, ':').collect::<Vec<_>>()[..] {
+            [kid, alg @ "hs256", _] => write!(f, "\"{}:{}:<redacted>\"", kid, alg),
+            _ => write!(f, "{:?}", self.0),
+        }
+    }
+}
+
+// same for the client secret of an oidc provider
+#[derive(PartialEq, Clone)]
+pub struct OidcProviderSpec(String);
+
+impl From<String> for OidcProviderSpec {
+    fn from(spec: String) -> Self {
+        Self(spec)
+    }
+}
+
+impl std::ops::Deref for OidcProviderSpec {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for OidcProviderSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fields: Vec<_> = self
+            .0
+            .split(',')
+            .map(|pair| match pair.split_once('=') {
+                Some(("client_secret", _)) => "client_secret=<redacted>",
+                _ => pair,
+            })
+            .collect();
+        write!(f, "{:?}", fields.join(","))
+    }
+}
+
+#[derive(clap::ValueEnum, PartialEq, Debug, Clone, Copy)]
+pub enum AuthMode {
+    Opaque,
+    Jwt,
+}
+
+#[derive(clap::ValueEnum, PartialEq, Debug, Clone, Copy)]
+#[value(rename_all = "snake_case")]
+pub enum RegistrationPolicy {
+    Open,
+    InviteOnly,
+    Closed,
+    DomainAllowlist,
+}
+
+#[derive(clap::Args, PartialEq, Debug, Clone)]
+pub struct RateLimitConfig {
+    #[arg(value_parser = |arg: &str| -> Result<Duration, ParseIntError> {Ok(Duration::from_secs(arg.parse()?))})]
+    #[clap(long, default_value = "60")]
+    pub rate_limit_window: Duration,
+
+    /// requests per window to the token endpoints and signup, 0 disables the limit
+    #[clap(long, default_value = "20")]
+    pub rate_limit_auth: u32,
+
+    /// GET requests per window, 0 disables the limit
+    #[clap(long, default_value = "600")]
+    pub rate_limit_read: u32,
+
+    /// other requests per window, 0 disables the limit
+    #[clap(long, default_value = "120")]
+    pub rate_limit_write: u32,
+
+    /// what the read and write limits are keyed by, the auth limit is always keyed by client ip.
+    /// Opaque tokens count against the client ip until the auth cache holds them
+    #[clap(long, value_enum, default_value = "user")]
+    pub rate_limit_key: RateLimitKey,
+}
+
+#[derive(clap::ValueEnum, PartialEq, Debug, Clone, Copy)]
+pub enum RateLimitKey {
+    Ip,
+    User,
+}
+
+#[derive(clap::Args, PartialEq, Debug, Clone)]
+pub struct OidcConfig {
+    /// name=<name>,issuer=<url>,client_id=<id>,client_secret=<secret>,redirect_uri=<url>
+    #[clap(long = "oidc-provider")]
+    pub oidc_providers: Vec<OidcProviderSpec>,
+}
+
+#[derive(clap::Args, PartialEq, Debug, Clone)]
+pub struct SweepConfig {
+    /// seconds between sweeps of expired tokens, grants and invitations and never activated accounts
+    #[arg(value_parser = |arg: &str| -> Result<Duration, ParseIntError> {Ok(Duration::from_secs(arg.parse()?))})]
+    #[clap(long, default_value = "300")]
+    pub sweep_interval: Duration,
+
+    /// rows deleted per statement while sweeping
+    #[clap(long, default_value = "1000")]
+    pub sweep_batch_size: u32,
+
+    /// seconds a never activated account is kept before it is deleted, 0 keeps them forever
+    #[arg(value_parser = |arg: &str| -> Result<Duration, ParseIntError> {Ok(Duration::from_secs(arg.parse()?))})]
+    #[clap(long, default_value = "604800")]
+    pub unactivated_account_ttl: Duration,
+}
+
+impl AuthConfig {
+    pub fn access_ttl(&self) -> chrono::Duration {
+        chrono::Duration::from_std(self.access_token_ttl).unwrap_or(chrono::Duration::hours(24))
+    }
+
+    pub fn refresh_ttl(&self) -> chrono::Duration {
+        chrono::Duration::from_std(self.refresh_token_ttl).unwrap_or(chrono::Duration::days(30))
+    }
+
+    pub fn impersonation_ttl(&self) -> chrono::Duration {
+        chrono::Duration::from_std(self.impersonation_ttl).unwrap_or(chrono::Duration::minutes(15))
+    }
+
+    pub fn jwt_ttl(&self) -> chrono::Duration {
+        chrono::Duration::from_std(self.jwt_token_ttl).unwrap_or(chrono::Duration::minutes(15))
+    }
+
+    pub fn argon2_params(&self) -> Result<argon2::Params, Error> {
+        argon2::Params::new(self.argon2_memory, self.argon2_iterations, self.argon2_parallelism, None)
+            .map_err(|e| Error::InvalidConfig(format!("argon2 params: {}", e)))
+    }
+}
 
 impl Config {
     pub fn new() -> Result<Config, Error> {
@@ -119,6 +369,176 @@ impl Config {
         let mail_password = std::env::var("GREENLIGHT_MAIL_PASSWORD")
             .ok()
             .unwrap_or(config.mail.mail_password);
+
+        let access_token_ttl = std::env::var("GREENLIGHT_ACCESS_TOKEN_TTL")
+            .ok()
+            .map(|val| val.parse::<u64>().map(Duration::from_secs))
+            .unwrap_or(Ok(config.auth.access_token_ttl))
+            .map_err(Error::ConfigParse)?;
+
+        let refresh_token_ttl = std::env::var("GREENLIGHT_REFRESH_TOKEN_TTL")
+            .ok()
+            .map(|val| val.parse::<u64>().map(Duration::from_secs))
+            .unwrap_or(Ok(config.auth.refresh_token_ttl))
+            .map_err(Error::ConfigParse)?;
+
+        let auth_mode = std::env::var("GREENLIGHT_AUTH_MODE")
+            .ok()
+            .map(|val| AuthMode::from_str(&val, true))
+            .unwrap_or(Ok(config.auth.auth_mode))
+            .map_err(Error::InvalidConfig)?;
+
+        let jwt_keys = std::env::var("GREENLIGHT_JWT_KEYS")
+            .ok()
+            .map(|val| val.split(',').map(|spec| JwtKeySpec::from(spec.to_owned())).collect())
+            .unwrap_or(config.auth.jwt_keys);
+
+        let jwt_token_ttl = std::env::var("GREENLIGHT_JWT_TOKEN_TTL")
+            .ok()
+            .map(|val| val.parse::<u64>().map(Duration::from_secs))
+            .unwrap_or(Ok(config.auth.jwt_token_ttl))
+            .map_err(Error::ConfigParse)?;
+
+        let login_max_attempts = std::env::var("GREENLIGHT_LOGIN_MAX_ATTEMPTS")
+            .ok()
+            .map(|val| val.parse::<u32>())
+            .unwrap_or(Ok(config.auth.login_max_attempts))
+            .map_err(Error::ConfigParse)?;
+
+        let login_ip_max_attempts = std::env::var("GREENLIGHT_LOGIN_IP_MAX_ATTEMPTS")
+            .ok()
+            .map(|val| val.parse::<u32>())
+            .unwrap_or(Ok(config.auth.login_ip_max_attempts))
+            .map_err(Error::ConfigParse)?;
+
+        let login_lockout = std::env::var("GREENLIGHT_LOGIN_LOCKOUT")
+            .ok()
+            .map(|val| val.parse::<u64>().map(Duration::from_secs))
+            .unwrap_or(Ok(config.auth.login_lockout))
+            .map_err(Error::ConfigParse)?;
+
+        let argon2_memory = std::env::var("GREENLIGHT_ARGON2_MEMORY")
+            .ok()
+            .map(|val| val.parse::<u32>())
+            .unwrap_or(Ok(config.auth.argon2_memory))
+            .map_err(Error::ConfigParse)?;
+
+        let argon2_iterations = std::env::var("GREENLIGHT_ARGON2_ITERATIONS")
+            .ok()
+            .map(|val| val.parse::<u32>())
+            .unwrap_or(Ok(config.auth.argon2_iterations))
+            .map_err(Error::ConfigParse)?;
+
+        let argon2_parallelism = std::env::var("GREENLIGHT_ARGON2_PARALLELISM")
+            .ok()
+            .map(|val| val.parse::<u32>())
+            .unwrap_or(Ok(config.auth.argon2_parallelism))
+            .map_err(Error::ConfigParse)?;
+
+        let breached_password_file = std::env::var("GREENLIGHT_BREACHED_PASSWORD_FILE")
+            .ok()
+            .or(config.auth.breached_password_file);
+
+        let default_role = std::env::var("GREENLIGHT_DEFAULT_ROLE")
+            .ok()
+            .unwrap_or(config.auth.default_role);
+
+        let policy_file = std::env::var("GREENLIGHT_POLICY_FILE")
+            .ok()
+            .or(config.auth.policy_file);
+
+        let auth_cache_ttl = std::env::var("GREENLIGHT_AUTH_CACHE_TTL")
+            .ok()
+            .map(|val| val.parse::<u64>().map(Duration::from_secs))
+            .unwrap_or(Ok(config.auth.auth_cache_ttl))
+            .map_err(Error::ConfigParse)?;
+
+        let registration_policy = std::env::var("GREENLIGHT_REGISTRATION_POLICY")
+            .ok()
+            .map(|val| RegistrationPolicy::from_str(&val, true))
+            .unwrap_or(Ok(config.auth.registration_policy))
+            .map_err(Error::InvalidConfig)?;
+
+        let allowed_email_domains = std::env::var("GREENLIGHT_ALLOWED_EMAIL_DOMAINS")
+            .ok()
+            .map(|val| val.split(',').map(str::to_owned).collect())
+            .unwrap_or(config.auth.allowed_email_domains);
+
+        let disposable_domains_file = std::env::var("GREENLIGHT_DISPOSABLE_DOMAINS_FILE")
+            .ok()
+            .or(config.auth.disposable_domains_file);
+
+        let impersonation_ttl = std::env::var("GREENLIGHT_IMPERSONATION_TTL")
+            .ok()
+            .map(|val| val.parse::<u64>().map(Duration::from_secs))
+            .unwrap_or(Ok(config.auth.impersonation_ttl))
+            .map_err(Error::ConfigParse)?;
+
+        let enumeration_protection = std::env::var("GREENLIGHT_ENUMERATION_PROTECTION")
+            .ok()
+            .map(|val| val.parse::<bool>().map_err(|_| format!("GREENLIGHT_ENUMERATION_PROTECTION must be true or false, got {val}")))
+            .unwrap_or(Ok(config.auth.enumeration_protection))
+            .map_err(Error::InvalidConfig)?;
+
+        let password_history = std::env::var("GREENLIGHT_PASSWORD_HISTORY")
+            .ok()
+            .map(|val| val.parse::<u32>())
+            .unwrap_or(Ok(config.auth.password_history))
+            .map_err(Error::ConfigParse)?;
+
+        // providers are separated by `;` since their fields are separated by `,`
+        let oidc_providers = std::env::var("GREENLIGHT_OIDC_PROVIDERS")
+            .ok()
+            .map(|val| val.split(';').map(|spec| OidcProviderSpec::from(spec.to_owned())).collect())
+            .unwrap_or(config.oidc.oidc_providers);
+
+        let rate_limit_window = std::env::var("GREENLIGHT_RATE_LIMIT_WINDOW")
+            .ok()
+            .map(|val| val.parse::<u64>().map(Duration::from_secs))
+            .unwrap_or(Ok(config.rate_limit.rate_limit_window))
+            .map_err(Error::ConfigParse)?;
+
+        let rate_limit_auth = std::env::var("GREENLIGHT_RATE_LIMIT_AUTH")
+            .ok()
+            .map(|val| val.parse::<u32>())
+            .unwrap_or(Ok(config.rate_limit.rate_limit_auth))
+            .map_err(Error::ConfigParse)?;
+
+        let rate_limit_read = std::env::var("GREENLIGHT_RATE_LIMIT_READ")
+            .ok()
+            .map(|val| val.parse::<u32>())
+            .unwrap_or(Ok(config.rate_limit.rate_limit_read))
+            .map_err(Error::ConfigParse)?;
+
+        let rate_limit_write = std::env::var("GREENLIGHT_RATE_LIMIT_WRITE")
+            .ok()
+            .map(|val| val.parse::<u32>())
+            .unwrap_or(Ok(config.rate_limit.rate_limit_write))
+            .map_err(Error::ConfigParse)?;
+
+        let rate_limit_key = std::env::var("GREENLIGHT_RATE_LIMIT_KEY")
+            .ok()
+            .map(|val| RateLimitKey::from_str(&val, true))
+            .unwrap_or(Ok(config.rate_limit.rate_limit_key))
+            .map_err(Error::InvalidConfig)?;
+
+        let sweep_interval = std::env::var("GREENLIGHT_SWEEP_INTERVAL")
+            .ok()
+            .map(|val| val.parse::<u64>().map(Duration::from_secs))
+            .unwrap_or(Ok(config.sweep.sweep_interval))
+            .map_err(Error::ConfigParse)?;
+
+        let sweep_batch_size = std::env::var("GREENLIGHT_SWEEP_BATCH_SIZE")
+            .ok()
+            .map(|val| val.parse::<u32>())
+            .unwrap_or(Ok(config.sweep.sweep_batch_size))
+            .map_err(Error::ConfigParse)?;
+
+        let unactivated_account_ttl = std::env::var("GREENLIGHT_UNACTIVATED_ACCOUNT_TTL")
+            .ok()
+            .map(|val| val.parse::<u64>().map(Duration::from_secs))
+            .unwrap_or(Ok(config.sweep.unactivated_account_ttl))
+            .map_err(Error::ConfigParse)?;
             
         Ok(Config {
             log_level: config.log_level,
@@ -126,8 +546,72 @@ impl Config {
             port,  
             redis_url,
             pg : DbConfig { db_dsn,  db_max_conn: max_conn, db_connect_timeout: connect_timeout },
-            mail: MailConfig { mail_sender, mail_host, mail_port, mail_username, mail_password }
+            mail: MailConfig { mail_sender, mail_host, mail_port, mail_username, mail_password },
+            auth: AuthConfig {
+                access_token_ttl,
+                refresh_token_ttl,
+                auth_mode,
+                jwt_keys,
+                jwt_token_ttl,
+                login_max_attempts,
+                login_ip_max_attempts,
+                login_lockout,
+                argon2_memory,
+                argon2_iterations,
+                argon2_parallelism,
+                breached_password_file,
+                default_role,
+                policy_file,
+                auth_cache_ttl,
+                registration_policy,
+                allowed_email_domains,
+                disposable_domains_file,
+                impersonation_ttl,
+                enumeration_protection,
+                password_history,
+            },
+            rate_limit: RateLimitConfig {
+                rate_limit_window,
+                rate_limit_auth,
+                rate_limit_read,
+                rate_limit_write,
+                rate_limit_key,
+            },
+            oidc: OidcConfig { oidc_providers },
+            sweep: SweepConfig {
+                sweep_interval,
+                sweep_batch_size,
+                unactivated_account_ttl,
+            },
         })
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::{JwtKeySpec, OidcProviderSpec};
+
+    #[test]
+    fn hs256_secret_is_redacted() {
+        let spec = JwtKeySpec::from("k1:hs256:s3cr3t:with:colons".to_owned());
+        let shown = format!("{:?}", spec);
+        assert_eq!(shown, r#""k1:hs256:<redacted>""#);
+        assert_eq!(&*spec, "k1:hs256:s3cr3t:with:colons");
+    }
+
+    #[test]
+    fn oidc_client_secret_is_redacted() {
+        let spec = OidcProviderSpec::from("name=acme,client_id=app,client_secret=s3cr3t,redirect_uri=http://app/cb".to_owned());
+        assert_eq!(
+            format!("{:?}", spec),
+            r#""name=acme,client_id=app,client_secret=<redacted>,redirect_uri=http://app/cb""#
+        );
+    }
+
+    #[test]
+    fn eddsa_pem_paths_are_shown() {
+        let spec = JwtKeySpec::from("k2:eddsa:/keys/private.pem:/keys/public.pem".to_owned());
+        assert_eq!(format!("{:?}", spec), r#""k2:eddsa:/keys/private.pem:/keys/public.pem""#);
+    }
+}
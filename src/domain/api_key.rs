@@ -0,0 +1,161 @@
+use chrono::{DateTime, Utc};
+use data_encoding::BASE32_NOPAD;
+use rand::RngCore;
+use std::collections::HashMap;
+
+use super::token::Token;
+use crate::validator::Validator;
+
+#[derive(Debug, serde::Serialize)]
+pub struct ServiceAccount {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub name: String,
+    pub created_by: Option<i64>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ServiceAccountJson {
+    pub name: String,
+}
+
+impl ServiceAccountJson {
+    pub fn validate(&self) -> Result<(), HashMap<&'static str, &'static str>> {
+        let mut v = Validator::new();
+        validate_name(&mut v, &self.name);
+        if !v.valid() {
+            Err(v.get_err())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct ApiKey {
+    pub id: i64,
+
+    #[serde(skip)]
+    pub service_account_id: i64,
+
+    #[serde(rename = "key", skip_serializing_if = "String::is_empty")]
+    pub plain_text: String,
+
+    #[serde(skip)]
+    pub hash: Vec<u8>,
+
+    pub name: String,
+    pub permissions: Vec<String>,
+    pub expiry: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl ApiKey {
+    pub fn new(
+        service_account_id: i64,
+        name: String,
+        permissions: Vec<String>,
+        expiry: Option<DateTime<Utc>>,
+    ) -> Self {
+        let mut buf = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut buf[..]);
+        let key = BASE32_NOPAD.encode(&buf);
+
+        Self {
+            service_account_id,
+            hash: Token::gen_hash(&key),
+            plain_text: key,
+            name,
+            permissions,
+            expiry,
+            created_at: Utc::now(),
+            ..Self::default()
+        }
+    }
+
+    pub fn validate(v: &mut Validator, plain_text: impl AsRef<str>) {
+        v.check(!plain_text.as_ref().is_empty(), "key", "must be provided");
+        v.check(plain_text.as_ref().len() == 52, "key", "must be 52 bytes long");
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ApiKeyJson {
+    pub name: String,
+    pub permissions: Vec<String>,
+    #[serde(default)]
+    pub expiry: Option<DateTime<Utc>>,
+}
+
+impl ApiKeyJson {
+    pub fn validate(&self, v: &mut Validator, granter_perms: &[String]) {
+        validate_name(v, &self.name);
+        v.check(
+            !self.permissions.is_empty(),
+            "permissions",
+            "must contain at least 1 permission",
+        );
+        v.check(
+            !(1..self.permissions.len()).any(|i| self.permissions[i..].contains(&self.permissions[i - 1])),
+            "permissions",
+            "must not contain duplicate values",
+        );
+        v.check(
+            self.permissions.iter().all(|p| granter_perms.contains(p)),
+            "permissions",
+            "must be a subset of your own permissions",
+        );
+        if let Some(expiry) = self.expiry {
+            v.check(expiry > Utc::now(), "expiry", "must be in the future");
+        }
+    }
+}
+
+fn validate_name(v: &mut Validator, name: &str) {
+    v.check(!name.trim().is_empty(), "name", "must be provided");
+    v.check(name.len() <= 128, "name", "must not be more than 128 bytes long");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ApiKey, ApiKeyJson};
+    use crate::validator::Validator;
+    use chrono::{Duration, Utc};
+
+    fn perms(codes: &[&str]) -> Vec<String> {
+        codes.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn generated_key_passes_validation() {
+        let key = ApiKey::new(1, "batch".to_owned(), perms(&["movies:read"]), None);
+        let mut v = Validator::new();
+        ApiKey::validate(&mut v, &key.plain_text);
+        assert!(v.valid());
+    }
+
+    #[test]
+    fn permissions_outside_granter_set_are_rejected() {
+        let input = ApiKeyJson {
+            name: "batch".to_owned(),
+            permissions: perms(&["movies:write"]),
+            expiry: None,
+        };
+        let mut v = Validator::new();
+        input.validate(&mut v, &perms(&["movies:read"]));
+        assert!(!v.valid());
+    }
+
+    #[test]
+    fn expiry_in_the_past_is_rejected() {
+        let input = ApiKeyJson {
+            name: "batch".to_owned(),
+            permissions: perms(&["movies:read"]),
+            expiry: Some(Utc::now() - Duration::days(1)),
+        };
+        let mut v = Validator::new();
+        input.validate(&mut v, &perms(&["movies:read"]));
+        assert!(!v.valid());
+    }
+}
@@ -0,0 +1,136 @@
+use chrono::{DateTime, Duration, Utc};
+use data_encoding::BASE32_NOPAD;
+use rand::RngCore;
+
+use super::email::Email;
+use super::token::Token;
+use crate::validator::Validator;
+
+// invitations nobody picked an expiry for stay open this long
+const DEFAULT_TTL_DAYS: i64 = 7;
+
+// An admin's offer to create an account for one email address. Accepting it
+// activates the account right away and grants the permissions it carries.
+#[derive(Debug, Default, serde::Serialize)]
+pub struct Invitation {
+    pub id: i64,
+
+    #[serde(skip)]
+    pub plain_text: String,
+
+    #[serde(skip)]
+    pub hash: Vec<u8>,
+
+    pub email: String,
+    pub permissions: Vec<String>,
+    pub expiry: DateTime<Utc>,
+    pub invited_by: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Invitation {
+    pub fn new(email: String, permissions: Vec<String>, expiry: DateTime<Utc>, invited_by: Option<i64>) -> Self {
+        let mut buf = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut buf[..]);
+        let token = BASE32_NOPAD.encode(&buf);
+
+        Self {
+            hash: Token::gen_hash(&token),
+            plain_text: token,
+            email,
+            permissions,
+            expiry,
+            invited_by,
+            created_at: Utc::now(),
+            ..Self::default()
+        }
+    }
+
+    // the token has the same shape as the other one-time tokens
+    pub fn validate(v: &mut Validator, plain_text: impl AsRef<str>) {
+        v.check(!plain_text.as_ref().is_empty(), "invitation", "must be provided");
+        v.check(plain_text.as_ref().len() == 26, "invitation", "must be 26 bytes long");
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct InvitationJson {
+    pub email: String,
+    pub permissions: Vec<String>,
+    #[serde(default)]
+    pub expiry: Option<DateTime<Utc>>,
+}
+
+impl InvitationJson {
+    pub fn expiry(&self) -> DateTime<Utc> {
+        self.expiry.unwrap_or_else(|| Utc::now() + Duration::days(DEFAULT_TTL_DAYS))
+    }
+
+    // codes are granted directly, so unlike role patterns they must match exactly
+    pub fn validate(&self, v: &mut Validator, known_codes: &[String]) {
+        if let Err(e) = Email::parse(self.email.clone()) {
+            v.add_err("email", e);
+        }
+        v.check(
+            !(1..self.permissions.len()).any(|i| self.permissions[i..].contains(&self.permissions[i - 1])),
+            "permissions",
+            "must not contain duplicate values",
+        );
+        v.check(
+            self.permissions.iter().all(|p| known_codes.contains(p)),
+            "permissions",
+            "must only contain known permission codes",
+        );
+        if let Some(expiry) = self.expiry {
+            v.check(expiry > Utc::now(), "expiry", "must be in the future");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Invitation, InvitationJson};
+    use crate::validator::Validator;
+    use chrono::{Duration, Utc};
+
+    fn known() -> Vec<String> {
+        vec!["movies:read".to_owned(), "movies:write:own".to_owned()]
+    }
+
+    #[test]
+    fn generated_token_passes_validation() {
+        let inv = Invitation::new("a@example.com".to_owned(), vec![], Utc::now(), None);
+        let mut v = Validator::new();
+        Invitation::validate(&mut v, &inv.plain_text);
+        assert!(v.valid());
+    }
+
+    #[test]
+    fn wildcards_and_unknown_codes_are_rejected() {
+        for code in ["movies:*", "movies:delete"] {
+            let input = InvitationJson {
+                email: "a@example.com".to_owned(),
+                permissions: vec![code.to_owned()],
+                expiry: None,
+            };
+            let mut v = Validator::new();
+            input.validate(&mut v, &known());
+            assert!(v.get_err().contains_key("permissions"));
+        }
+    }
+
+    #[test]
+    fn past_expiry_is_rejected_and_a_missing_one_defaulted() {
+        let input = InvitationJson {
+            email: "a@example.com".to_owned(),
+            permissions: known(),
+            expiry: Some(Utc::now() - Duration::minutes(1)),
+        };
+        let mut v = Validator::new();
+        input.validate(&mut v, &known());
+        assert!(v.get_err().contains_key("expiry"));
+
+        let input = InvitationJson { expiry: None, ..input };
+        assert!(input.expiry() > Utc::now());
+    }
+}
@@ -0,0 +1,71 @@
+// Languages the transactional emails are written in. Anything else falls back
+// to English, so adding one only takes a variant here and its templates.
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    Es,
+    Fr,
+}
+
+impl Locale {
+    pub const SUPPORTED: &'static [Locale] = &[Locale::En, Locale::Es, Locale::Fr];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::Es => "es",
+            Locale::Fr => "fr",
+        }
+    }
+
+    // only the primary subtag matters, `es-MX` reads the `es` emails
+    pub fn parse(tag: &str) -> Option<Locale> {
+        let primary = tag.trim().split(['-', '_']).next().unwrap_or_default();
+        Self::SUPPORTED
+            .iter()
+            .copied()
+            .find(|l| l.as_str().eq_ignore_ascii_case(primary))
+    }
+
+    // picks the supported language the client weighs highest, ties keep header order
+    pub fn negotiate(accept_language: &str) -> Option<Locale> {
+        let mut best: Option<(Locale, f32)> = None;
+        for item in accept_language.split(',') {
+            let mut parts = item.split(';');
+            let tag = parts.next().unwrap_or_default();
+            let q = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok());
+            let (Some(locale), Some(q)) = (Self::parse(tag), q) else {
+                continue;
+            };
+            if q > 0.0 && best.is_none_or(|(_, b)| q > b) {
+                best = Some((locale, q));
+            }
+        }
+        best.map(|(locale, _)| locale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Locale;
+    use claims::{assert_none, assert_some_eq};
+
+    #[test]
+    fn region_subtags_are_ignored() {
+        assert_some_eq!(Locale::parse("es-MX"), Locale::Es);
+        assert_some_eq!(Locale::parse("FR"), Locale::Fr);
+        assert_none!(Locale::parse("de"));
+    }
+
+    #[test]
+    fn highest_weighted_supported_language_wins() {
+        assert_some_eq!(Locale::negotiate("de-DE, fr;q=0.8, es;q=0.9"), Locale::Es);
+        assert_some_eq!(Locale::negotiate("fr-CA,fr;q=0.9,en;q=0.8"), Locale::Fr);
+        assert_none!(Locale::negotiate("de, *;q=0.5"));
+        assert_none!(Locale::negotiate("es;q=0"));
+    }
+}
@@ -7,5 +7,12 @@ pub mod user;
 pub mod filter;
 pub mod movie;
 pub mod token;
+pub mod session;
+pub mod api_key;
+pub mod totp;
+pub mod role;
+pub mod invitation;
+pub mod locale;
 pub use email::Email;
+pub use user_name::UserName;
 
@@ -37,6 +37,23 @@ pub struct Movie {
 
     #[serde(skip_deserializing)]
     pub version: i32,
+
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub created_by: Option<i64>,
+}
+
+impl Movie {
+    // what authorization policies see as `resource.*`
+    pub fn attributes(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.id,
+            "title": self.title,
+            "year": self.year,
+            "runtime": self.runtime.as_ref(),
+            "genres": self.genres,
+            "created_by": self.created_by,
+        })
+    }
 }
 
 fn is_zero(num: &i32) -> bool {
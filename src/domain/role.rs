@@ -0,0 +1,137 @@
+use chrono::{DateTime, Utc};
+
+use crate::validator::Validator;
+
+#[derive(Debug, serde::Serialize)]
+pub struct Role {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub name: String,
+    pub permissions: Vec<String>,
+}
+
+// a permission granted to a user directly rather than through a role
+#[derive(Debug, serde::Serialize)]
+pub struct Grant {
+    pub code: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub granted_by: Option<i64>,
+}
+
+#[derive(serde::Deserialize, Debug, Default)]
+pub struct GrantJson {
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl GrantJson {
+    pub fn validate(&self, v: &mut Validator) {
+        if let Some(expires_at) = self.expires_at {
+            v.check(expires_at > Utc::now(), "expires_at", "must be in the future");
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct RoleJson {
+    pub name: String,
+    pub permissions: Vec<String>,
+}
+
+impl RoleJson {
+    pub fn validate(&self, v: &mut Validator, known_codes: &[String]) {
+        validate_name(v, &self.name);
+        v.check(
+            !self.permissions.is_empty(),
+            "permissions",
+            "must contain at least 1 permission",
+        );
+        v.check(
+            !(1..self.permissions.len()).any(|i| self.permissions[i..].contains(&self.permissions[i - 1])),
+            "permissions",
+            "must not contain duplicate values",
+        );
+        // a pattern that grants nothing is most likely a typo
+        v.check(
+            self.permissions.iter().all(|p| known_codes.iter().any(|code| grants(p, code))),
+            "permissions",
+            "must only contain known permission codes or wildcards matching them",
+        );
+    }
+}
+
+// role names end up in request paths, so they are kept to a url safe alphabet
+pub fn validate_name(v: &mut Validator, name: &str) {
+    v.check(!name.is_empty(), "name", "must be provided");
+    v.check(name.len() <= 64, "name", "must not be more than 64 bytes long");
+    v.check(
+        name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_'),
+        "name",
+        "must only contain lowercase letters, digits, '-' and '_'",
+    );
+}
+
+// `movies:*` grants every code under `movies:` and a lone `*` grants everything,
+// the store resolves role patterns with the same rule
+pub fn grants(pattern: &str, code: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => (prefix.is_empty() || prefix.ends_with(':')) && code.starts_with(prefix),
+        None => pattern == code,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{grants, GrantJson, RoleJson};
+    use crate::validator::Validator;
+    use chrono::{Duration, Utc};
+
+    fn codes(codes: &[&str]) -> Vec<String> {
+        codes.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn wildcard_grants_codes_under_its_prefix() {
+        assert!(grants("movies:*", "movies:read"));
+        assert!(grants("movies:*", "movies:write"));
+        assert!(!grants("movies:*", "users:admin"));
+        assert!(grants("*", "users:admin"));
+        assert!(grants("movies:read", "movies:read"));
+        assert!(!grants("movies:read", "movies:write"));
+        // only whole segments are wildcarded
+        assert!(!grants("mov*", "movies:read"));
+    }
+
+    #[test]
+    fn patterns_matching_no_known_code_are_rejected() {
+        let known = codes(&["movies:read", "movies:write"]);
+        let input = RoleJson { name: "editor".to_owned(), permissions: codes(&["movies:*"]) };
+        let mut v = Validator::new();
+        input.validate(&mut v, &known);
+        assert!(v.valid());
+
+        let input = RoleJson { name: "editor".to_owned(), permissions: codes(&["movie:*"]) };
+        let mut v = Validator::new();
+        input.validate(&mut v, &known);
+        assert!(!v.valid());
+    }
+
+    #[test]
+    fn role_name_must_be_url_safe() {
+        let input = RoleJson { name: "Movie Editors".to_owned(), permissions: codes(&["movies:read"]) };
+        let mut v = Validator::new();
+        input.validate(&mut v, &codes(&["movies:read"]));
+        assert!(!v.valid());
+    }
+
+    #[test]
+    fn grant_expiry_must_be_in_the_future() {
+        let mut v = Validator::new();
+        GrantJson { expires_at: Some(Utc::now() - Duration::hours(1)) }.validate(&mut v);
+        assert!(!v.valid());
+
+        let mut v = Validator::new();
+        GrantJson::default().validate(&mut v);
+        assert!(v.valid());
+    }
+}
@@ -68,29 +68,3 @@ impl<'de> Deserialize<'de> for RunTime {
         RunTime::from_str(s).map_err(Error::custom)
     }
 }
-
-#[derive(Serialize, Deserialize, Debug)]
-struct W {
-    a: i32,
-    b: String,
-    c: RunTime,
-}
-
-/*
-fn main() {
-    let r = Runtime(30);
-    println!("{}", serde_json::to_string(&r).unwrap());
-
-    println!("{:?}", serde_json::from_str::<Runtime>("\"180 mins\"").unwrap());
-
-    let w = W {
-        a:  104,   b:  "hello".to_owned(),  c: r,
-    };
-
-    println!("{}", serde_json::to_string(&w).unwrap());
-
-    let input = r#"{"a":104,"b":"hello","c":"180 mins"}"#;
-    println!("{:?}", serde_json::from_str::<W>(input).unwrap())
-   
-}
-*/
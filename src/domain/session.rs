@@ -0,0 +1,106 @@
+use chrono::{DateTime, Utc};
+use data_encoding::BASE32_NOPAD;
+
+const SESSION_ID_BYTES: usize = 10;
+
+#[derive(Debug, Default, Clone)]
+pub struct ClientInfo {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Subject {
+    User(i64),
+    ServiceAccount(i64),
+}
+
+pub struct Principal {
+    pub subject: Subject,
+    pub permissions: Vec<String>,
+    pub roles: Vec<String>,
+    // absent when the request was authenticated by a signed access token or an api key
+    pub token_hash: Option<Vec<u8>>,
+    // the admin acting as this user, see `handlers::admin::impersonate`
+    pub impersonator: Option<i64>,
+}
+
+impl Principal {
+    pub fn user_id(&self) -> Option<i64> {
+        match self.subject {
+            Subject::User(id) => Some(id),
+            Subject::ServiceAccount(_) => None,
+        }
+    }
+
+    // an `:any` grant also covers the narrower `:own` scope of the same action
+    pub fn has(&self, code: &str) -> bool {
+        let any = code.strip_suffix(":own").map(|action| format!("{}:any", action));
+        self.permissions.iter().any(|p| p == code || Some(p) == any.as_ref())
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Session {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expiry: DateTime<Utc>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub current: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub impersonator_id: Option<i64>,
+}
+
+impl Session {
+    // the session id is a prefix of the token hash, never the token itself
+    pub fn id_of(token_hash: &[u8]) -> String {
+        BASE32_NOPAD.encode(&token_hash[..SESSION_ID_BYTES.min(token_hash.len())])
+    }
+
+    pub fn parse_id(id: impl AsRef<str>) -> Result<Vec<u8>, &'static str> {
+        match BASE32_NOPAD.decode(id.as_ref().as_bytes()) {
+            Ok(prefix) if prefix.len() == SESSION_ID_BYTES => Ok(prefix),
+            _ => Err("invalid session id"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Principal, Session, Subject};
+    use crate::token::Token;
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn session_id_round_trips_to_hash_prefix() {
+        let hash = Token::gen_hash("UGVGRUWXYK7FAMAHLJ3C2S6ETI");
+        let id = Session::id_of(&hash);
+        assert_ok_eq!(Session::parse_id(id), hash[..10].to_vec());
+    }
+
+    #[test]
+    fn malformed_session_id_is_rejected() {
+        assert_err!(Session::parse_id("not-base32!"));
+        assert_err!(Session::parse_id("AAAA"));
+    }
+
+    #[test]
+    fn any_scope_covers_own_scope() {
+        let principal = Principal {
+            subject: Subject::User(1),
+            permissions: vec!["movies:write:any".to_owned()],
+            roles: vec![],
+            token_hash: None,
+            impersonator: None,
+        };
+        assert!(principal.has("movies:write:own"));
+        assert!(principal.has("movies:write:any"));
+        assert!(!principal.has("movies:read"));
+
+        let principal = Principal { permissions: vec!["movies:write:own".to_owned()], ..principal };
+        assert!(principal.has("movies:write:own"));
+        assert!(!principal.has("movies:write:any"));
+    }
+}
@@ -3,11 +3,15 @@ use data_encoding::BASE32_NOPAD;
 use rand::RngCore;
 use sha2::{Digest, Sha256};
 
+use super::session::ClientInfo;
 use crate::validator::Validator;
 
 pub const SCOPE_ACTIVATION: &str = "activation";
 pub const SCOPE_AUTHENTICATION: &str = "authentication";
 pub const SCOPE_PASSWORDRESET: &str = "password-reset";
+pub const SCOPE_REFRESH: &str = "refresh";
+pub const SCOPE_MFA: &str = "mfa";
+pub const SCOPE_MAGICLINK: &str = "magic-link";
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct Token {
@@ -25,6 +29,36 @@ pub struct Token {
 
     #[serde(skip)]
     pub scope: &'static str,
+
+    #[serde(skip)]
+    pub created_at: DateTime<Utc>,
+
+    #[serde(skip)]
+    pub last_used_at: Option<DateTime<Utc>>,
+
+    #[serde(skip)]
+    pub ip: Option<String>,
+
+    #[serde(skip)]
+    pub user_agent: Option<String>,
+
+    #[serde(skip)]
+    pub family: Option<Vec<u8>>,
+
+    // set on access tokens an admin holds while acting as `user_id`
+    #[serde(skip)]
+    pub impersonator_id: Option<i64>,
+}
+
+// everything a protected request needs to know about its access token
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct TokenAuth {
+    pub user_id: i64,
+    pub activated: bool,
+    pub expiry: DateTime<Utc>,
+    pub impersonator_id: Option<i64>,
+    pub permissions: Vec<String>,
+    pub roles: Vec<String>,
 }
 
 impl Token {
@@ -35,16 +69,57 @@ impl Token {
         let token = BASE32_NOPAD.encode(&buf);
         //let token_hash = Sha256::digest(&token).to_vec();
         let token_hash  = Self::gen_hash(&token);
+        let now = Utc::now();
 
         Self {
             user_id,
             scope,
-            expiry: Utc::now() + ttl,
+            expiry: now + ttl,
             plain_text:  token,
             hash:  token_hash,
+            created_at: now,
+            last_used_at: None,
+            ip: None,
+            user_agent: None,
+            family: None,
+            impersonator_id: None,
+        }
+    }
+
+    // a token that is verified by its signature and never saved
+    pub fn stateless(user_id: i64, plain_text: String, expiry: DateTime<Utc>, scope: &'static str) -> Self {
+        Self {
+            user_id,
+            scope,
+            expiry,
+            hash: Self::gen_hash(&plain_text),
+            plain_text,
+            created_at: Utc::now(),
+            last_used_at: None,
+            ip: None,
+            user_agent: None,
+            family: None,
+            impersonator_id: None,
         }
     }
 
+    pub fn new_family() -> Vec<u8> {
+        let mut buf = vec![0u8; 16];
+        rand::thread_rng().fill_bytes(&mut buf[..]);
+        buf
+    }
+
+    pub fn with_family(mut self, family: Vec<u8>) -> Self {
+        self.family = Some(family);
+        self
+    }
+
+    pub fn with_client(mut self, client: &ClientInfo) -> Self {
+        self.ip = client.ip.clone();
+        self.user_agent = client.user_agent.clone();
+        self
+    }
+
     pub fn gen_hash(plain_text: impl AsRef<str>)  ->  Vec<u8>  {
         Sha256::digest(plain_text.as_ref()).to_vec()
     }
@@ -56,4 +131,7 @@ impl Token {
 
 }
 
-
+#[derive(serde::Deserialize)]
+pub struct RefreshJson {
+    pub refresh_token: String,
+}
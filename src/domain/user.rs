@@ -3,6 +3,7 @@ use secrecy::{ExposeSecret, Secret};
 use std::collections::HashMap;
 
 use super::email::Email;
+use super::locale::Locale;
 use super::user_name::UserName;
 use super::user_pass::UserPass;
 use super::token::Token;
@@ -20,6 +21,9 @@ pub struct User {
     pub password_hash: Secret<String>,
     pub activated: bool,
     #[serde(skip)]
+    pub activated_at: Option<DateTime<Utc>>,
+    pub locale: Locale,
+    #[serde(skip)]
     pub version: i32,
 }
 
@@ -28,6 +32,11 @@ pub struct SignupJson {
     pub name: String,
     pub email: String,
     pub password: String,
+    #[serde(default)]
+    pub invitation: Option<String>,
+    // wins over the Accept-Language header when both are present
+    #[serde(default)]
+    pub locale: Option<String>,
 }
 
 impl TryFrom<SignupJson> for User {
@@ -39,6 +48,7 @@ impl TryFrom<SignupJson> for User {
         let name = UserName::parse(value.name);
         let email = Email::parse(value.email);
         let pass = UserPass::parse(value.password);
+        let locale = value.locale.as_deref().map(Locale::parse);
 
         if let Err(name_err) = name {
             v.add_err("name", name_err);
@@ -49,6 +59,9 @@ impl TryFrom<SignupJson> for User {
         if let Err(pass_err) = pass {
             v.add_err("password", pass_err);
         }
+        if let Some(None) = locale {
+            v.add_err("locale", "must be one of en, es or fr");
+        }
 
         if !v.valid() {
             Err(v.get_err())
@@ -60,6 +73,8 @@ impl TryFrom<SignupJson> for User {
                 id: 0,
                 created_at: DateTime::<Utc>::default(),
                 activated: false,
+                activated_at: None,
+                locale: locale.flatten().unwrap_or_default(),
                 version: 0,
                 password_hash: Secret::new(String::default()),
             })
@@ -1,10 +1,11 @@
 use warp::{
     filters::{body::BodyDeserializeError, cors::CorsForbidden},
-    http::StatusCode,
+    http::{header::RETRY_AFTER, HeaderValue, StatusCode},
     reject::Reject,
     Rejection, Reply,
 };
 use tracing::instrument;
+use crate::rate_limit::Quota;
 use serde_json::json;
 use std::collections::HashMap;
 use std::convert::Infallible;
@@ -18,6 +19,9 @@ pub enum Error {
     #[error("parse config: {0}")]
     ConfigParse(#[source] std::num::ParseIntError),
 
+    #[error("invalid config: {0}")]
+    InvalidConfig(String),
+
     #[error("query database: {0}")]
     DatabaseQuery(#[from] sqlx::Error),
 
@@ -42,6 +46,9 @@ pub enum Error {
     #[error("your user account doesn't have the necessary permissions to access this resource")]
     Unauthorized,
 
+    #[error("credentials can't be changed while impersonating a user")]
+    Impersonating,
+
     #[error("invalid credentials")]
     InvalidCredentials,
 
@@ -51,6 +58,15 @@ pub enum Error {
     #[error("duplicate email")]
     DuplicateEmail,
 
+    #[error("duplicate name")]
+    DuplicateName,
+
+    #[error("too many requests, please try again later")]
+    TooManyRequests(u64),
+
+    #[error("rate limit exceeded, please slow down")]
+    RateLimited { limit: u64, reset: u64 },
+
     #[error("other kind unexpected error {0}")]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -66,6 +82,8 @@ pub async fn return_error(r: Rejection) -> Result<impl Reply, Infallible> {
         let mut status = StatusCode::INTERNAL_SERVER_ERROR;
         let r = "the server encountered a problem and could not process your request";
         let mut msg = json!({"error": r}).to_string(); 
+        let mut retry_after = None;
+        let mut rate_limit = None;
 
         match my {
             Error::Validation(e) => {
@@ -85,36 +103,51 @@ pub async fn return_error(r: Rejection) -> Result<impl Reply, Infallible> {
                 status = StatusCode::UNAUTHORIZED;
                 msg = json!({"error": my.to_string()}).to_string();
             }
-            Error::InactiveAccount | Error::Unauthorized   =>  {
+            Error::InactiveAccount | Error::Unauthorized | Error::Impersonating  =>  {
                 status = StatusCode::FORBIDDEN;
                 msg = json!({"error": my.to_string()}).to_string();
             }
+            Error::TooManyRequests(secs) => {
+                status = StatusCode::TOO_MANY_REQUESTS;
+                msg = json!({"error": my.to_string()}).to_string();
+                retry_after = Some(*secs);
+            }
+            Error::RateLimited { limit, reset } => {
+                status = StatusCode::TOO_MANY_REQUESTS;
+                msg = json!({"error": my.to_string()}).to_string();
+                retry_after = Some(*reset);
+                rate_limit = Some((*limit, *reset));
+            }
             _ =>  {
                 
             },
         }
-        Ok(warp::reply::with_status(
-            msg,
-            status,
-        )) 
+        let mut res = warp::reply::with_status(msg, status).into_response();
+        if let Some(secs) = retry_after {
+            res.headers_mut().insert(RETRY_AFTER, HeaderValue::from(secs));
+        }
+        if let Some((limit, reset)) = rate_limit {
+            Quota { limit, remaining: 0, reset, exceeded: true }.apply(res.headers_mut());
+        }
+        Ok(res)
     } else if let Some(error) = r.find::<CorsForbidden>() {
         tracing::error!("CORS forbidden error: {}", error);
         Ok(warp::reply::with_status(
             json!({"error":  error.to_string()}).to_string(),
             StatusCode::FORBIDDEN,
-        ))
+        ).into_response())
     } else if let Some(error) = r.find::<BodyDeserializeError>() {
         tracing::error!("Cannot deserizalize request body: {}", error);
         Ok(warp::reply::with_status(
             json!({"error":  error.to_string()}).to_string(),
             StatusCode::BAD_REQUEST,
-        ))
+        ).into_response())
     } else {
         tracing::warn!("Requested route was not found");
         Ok(warp::reply::with_status(
              json!({"error": "the requested resource could not be found"}).to_string(),
             StatusCode::NOT_FOUND,
-        ))
+        ).into_response())
     }
 }
 
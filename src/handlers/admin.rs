@@ -0,0 +1,429 @@
+use std::collections::HashMap;
+use tracing::instrument;
+use warp::http::StatusCode;
+use serde_json::json;
+use redis::Client;
+
+use crate::config::AuthConfig;
+use crate::errors::Error;
+use crate::filter::Filter;
+use crate::invitation::{Invitation, InvitationJson};
+use crate::mailer::{push_task, ImpersonationNotice, Invite, PasswordReset};
+use crate::role::{GrantJson, RoleJson};
+use crate::session::{ClientInfo, Principal};
+use crate::store::Store;
+use crate::token::{Token, SCOPE_AUTHENTICATION, SCOPE_PASSWORDRESET, SCOPE_REFRESH};
+use crate::validator::Validator;
+use super::session::session_scope;
+use super::token::gen_token_and_save;
+
+#[derive(serde::Deserialize, Debug)]
+pub struct UserStatusJson {
+    pub activated: bool,
+}
+
+#[instrument(skip(_principal))]
+pub async fn search_users(
+    qs: HashMap<String, String>,
+    store: Store,
+    _principal: Principal,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let query = qs.get("q").map(String::as_str).unwrap_or_default();
+
+    let mut v = Validator::new();
+    let activated = match qs.get("activated") {
+        None => None,
+        Some(e) => e.parse().map_err(|_| v.add_err("activated", "must be a boolean value")).ok(),
+    };
+    let page = match qs.get("page") {
+        None => 1i64,
+        Some(e) => {
+            e.parse().map_err(|_| v.add_err("page", "must be an integer value")).unwrap_or(1i64)
+        },
+    };
+    let page_size = match qs.get("page_size") {
+        None => 20i64,
+        Some(e) => {
+            e.parse().map_err(|_| v.add_err("page_size", "must be an integer value")).unwrap_or(20i64)
+        },
+    };
+
+    let sort = qs.get("sort").map(String::as_str).unwrap_or("id");
+
+    let filter = Filter {
+        page,
+        page_size,
+        sort,
+        sort_list: &["id", "name", "email", "created_at", "-id", "-name", "-email", "-created_at"],
+    };
+
+    filter.validate(&mut v);
+    if !v.valid() {
+        return Err(Error::Validation(v.get_err()).into());
+    }
+
+    let (meta, users) = store.search_users(query, activated, &filter).await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({"metadata": meta, "users": users})),
+        StatusCode::OK,
+    ))
+}
+
+#[instrument(skip(_principal))]
+pub async fn get_user(
+    id: i64,
+    store: Store,
+    config: AuthConfig,
+    _principal: Principal,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let user = store.get_user(id).await?;
+    let roles = store.roles_by_user(user.id).await?;
+    let grants = store.grants_by_user(user.id).await?;
+    let permissions = store.permissions_by_user(user.id).await?;
+    let sessions = store.sessions_by_user(user.id, session_scope(&config), None).await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({
+            "user": user,
+            "roles": roles,
+            "grants": grants,
+            "permissions": permissions,
+            "sessions": sessions,
+        })),
+        StatusCode::OK,
+    ))
+}
+
+#[instrument(skip(principal))]
+pub async fn set_user_status(
+    id: i64,
+    input: UserStatusJson,
+    store: Store,
+    principal: Principal,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if principal.user_id() == Some(id) && !input.activated {
+        let mut v = Validator::new();
+        v.add_err("activated", "you cannot deactivate your own account");
+        return Err(Error::Validation(v.get_err()).into());
+    }
+
+    let mut user = store.get_user(id).await?;
+    if user.activated != input.activated {
+        user.activated = input.activated;
+        store.update_user(&mut user).await?;
+    }
+    // a deactivated user is signed out everywhere, signed access tokens carry
+    // the activation state and run out on their own
+    if !user.activated {
+        store.delete_token(SCOPE_AUTHENTICATION, user.id).await?;
+        store.delete_token(SCOPE_REFRESH, user.id).await?;
+    }
+    tracing::info!(user_id = user.id, activated = user.activated, by = ?principal.subject, "user status changed");
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({"user": user})),
+        StatusCode::OK,
+    ))
+}
+
+#[instrument(skip(principal))]
+pub async fn force_password_reset(
+    id: i64,
+    store: Store,
+    redis: Client,
+    principal: Principal,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let user = store.get_user(id).await?;
+
+    let tok = gen_token_and_save(
+        store,
+        user.id,
+        chrono::Duration::minutes(45),
+        SCOPE_PASSWORDRESET,
+    )
+    .await?;
+
+    let task = PasswordReset::new(tok.plain_text, user.locale)
+        .gen_task(user.email.into())
+        .map_err(Error::Render)?;
+
+    push_task(&redis, &task).await.map_err(Error::UnexpectedError)?;
+    tracing::info!(user_id = user.id, by = ?principal.subject, "password reset forced");
+
+    let msg = json!({"message": "a password reset email will be sent to the user"});
+    Ok(warp::reply::with_status(
+        warp::reply::json(&msg),
+        StatusCode::ACCEPTED,
+    ))
+}
+
+// Hands an admin a short lived access token for another user. The token remembers
+// who asked for it, has no refresh token to outlive it, and the user is told.
+#[instrument(skip(store, redis, config, principal))]
+pub async fn impersonate(
+    id: i64,
+    client: ClientInfo,
+    store: Store,
+    redis: Client,
+    config: AuthConfig,
+    principal: Principal,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let Some(admin_id) = principal.user_id() else {
+        return Err(Error::Unauthorized.into());
+    };
+
+    let mut v = Validator::new();
+    v.check(admin_id != id, "id", "you cannot impersonate yourself");
+    if !v.valid() {
+        return Err(Error::Validation(v.get_err()).into());
+    }
+
+    let user = store.get_user(id).await?;
+    if !user.activated {
+        v.add_err("id", "must refer to an activated user");
+        return Err(Error::Validation(v.get_err()).into());
+    }
+
+    // acting as someone must never reach further than the admin already can
+    let target_perms = store.permissions_by_user(user.id).await?;
+    if let Some(code) = target_perms.iter().find(|code| !principal.has(code)) {
+        tracing::warn!(user_id = user.id, impersonator_id = admin_id, code, "impersonation refused, target holds more");
+        return Err(Error::Unauthorized.into());
+    }
+
+    let mut tok = Token::new(user.id, config.impersonation_ttl(), SCOPE_AUTHENTICATION).with_client(&client);
+    tok.impersonator_id = Some(admin_id);
+    store.save_token(&tok).await?;
+    tracing::info!(user_id = user.id, impersonator_id = admin_id, expiry = %tok.expiry, "impersonation started");
+
+    let admin = store.get_user(admin_id).await?;
+    let task = ImpersonationNotice::new(admin.name.to_string(), tok.created_at, tok.expiry)
+        .gen_task(user.email.clone().into())
+        .map_err(Error::Render)?;
+    push_task(&redis, &task).await.map_err(Error::UnexpectedError)?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({"authentication_token": tok, "user": user})),
+        StatusCode::CREATED,
+    ))
+}
+
+#[instrument(skip(principal))]
+pub async fn remove_user(
+    id: i64,
+    store: Store,
+    principal: Principal,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if principal.user_id() == Some(id) {
+        let mut v = Validator::new();
+        v.add_err("id", "you cannot delete your own account");
+        return Err(Error::Validation(v.get_err()).into());
+    }
+
+    let count = store.delete_user(id).await?;
+    if count == 0 {
+        return Err(Error::RecordNotFound.into());
+    }
+    tracing::info!(user_id = id, by = ?principal.subject, "user deleted");
+
+    let msg = json!({"message": "user successfully deleted"});
+    Ok(warp::reply::with_status(
+        warp::reply::json(&msg),
+        StatusCode::OK,
+    ))
+}
+
+#[instrument(skip(_principal))]
+pub async fn list_roles(
+    store: Store,
+    _principal: Principal,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let roles = store.list_roles().await?;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({"roles": roles})),
+        StatusCode::OK,
+    ))
+}
+
+#[instrument(skip(principal))]
+pub async fn add_role(
+    input: RoleJson,
+    store: Store,
+    principal: Principal,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut v = Validator::new();
+    input.validate(&mut v, &store.all_permissions().await?);
+    if !v.valid() {
+        return Err(Error::Validation(v.get_err()).into());
+    }
+
+    let ret = store.add_role(&input.name, &input.permissions).await;
+    if let Err(Error::DuplicateName) = ret {
+        v.add_err("name", "a role with this name already exists");
+        return Err(Error::Validation(v.get_err()).into());
+    }
+    let role = ret?;
+    tracing::info!(role = %role.name, by = ?principal.subject, "role created");
+
+    let loc = format!("/v1/admin/roles/{}", role.name);
+    Ok(warp::reply::with_status(
+        warp::reply::with_header(
+            warp::reply::json(&json!({"role": role})),
+            "Location",
+            loc,
+        ),
+        StatusCode::CREATED,
+    ))
+}
+
+#[instrument(skip(principal))]
+pub async fn assign_role(
+    id: i64,
+    role: String,
+    store: Store,
+    principal: Principal,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let user = store.get_user(id).await?;
+    let role = store.get_role(&role).await?;
+    store.assign_role(user.id, &role.name).await?;
+    tracing::info!(user_id = user.id, role = %role.name, by = ?principal.subject, "role assigned");
+
+    user_grants(&store, user.id).await
+}
+
+#[instrument(skip(principal))]
+pub async fn unassign_role(
+    id: i64,
+    role: String,
+    store: Store,
+    principal: Principal,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if store.unassign_role(id, &role).await? == 0 {
+        return Err(Error::RecordNotFound.into());
+    }
+    tracing::info!(user_id = id, role, by = ?principal.subject, "role unassigned");
+
+    user_grants(&store, id).await
+}
+
+#[instrument(skip(principal))]
+pub async fn grant_permission(
+    id: i64,
+    code: String,
+    input: GrantJson,
+    store: Store,
+    principal: Principal,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut v = Validator::new();
+    input.validate(&mut v);
+    if !v.valid() {
+        return Err(Error::Validation(v.get_err()).into());
+    }
+
+    let user = store.get_user(id).await?;
+    if !store.all_permissions().await?.contains(&code) {
+        return Err(Error::RecordNotFound.into());
+    }
+    store
+        .grant_permissions_to_user(user.id, &vec![code.clone()], input.expires_at, principal.user_id())
+        .await?;
+    tracing::info!(user_id = user.id, code, expires_at = ?input.expires_at, by = ?principal.subject, "permission granted");
+
+    user_grants(&store, user.id).await
+}
+
+// only removes a direct grant, a code the user also holds through a role stays
+#[instrument(skip(principal))]
+pub async fn revoke_permission(
+    id: i64,
+    code: String,
+    store: Store,
+    principal: Principal,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if store.revoke_permission_from_user(id, &code).await? == 0 {
+        return Err(Error::RecordNotFound.into());
+    }
+    tracing::info!(user_id = id, code, by = ?principal.subject, "permission revoked");
+
+    user_grants(&store, id).await
+}
+
+#[instrument(skip(_principal))]
+pub async fn list_invitations(
+    store: Store,
+    _principal: Principal,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let invitations = store.list_invitations().await?;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({"invitations": invitations})),
+        StatusCode::OK,
+    ))
+}
+
+// the token only ever travels in the invite email
+#[instrument(skip(redis, principal))]
+pub async fn invite_user(
+    input: InvitationJson,
+    store: Store,
+    redis: Client,
+    principal: Principal,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut v = Validator::new();
+    input.validate(&mut v, &store.all_permissions().await?);
+    if !v.valid() {
+        return Err(Error::Validation(v.get_err()).into());
+    }
+
+    if store.get_user_by_email(&input.email).await.is_ok() {
+        v.add_err("email", "a user with this email address already exists");
+        return Err(Error::Validation(v.get_err()).into());
+    }
+
+    let expiry = input.expiry();
+    let mut inv = Invitation::new(input.email, input.permissions, expiry, principal.user_id());
+    store.add_invitation(&mut inv).await?;
+    tracing::info!(invitation_id = inv.id, email = %inv.email, by = ?principal.subject, "invitation created");
+
+    let task = Invite::new(inv.email.clone(), inv.plain_text.clone(), inv.expiry)
+        .gen_task(inv.email.clone())
+        .map_err(Error::Render)?;
+    push_task(&redis, &task).await.map_err(Error::UnexpectedError)?;
+
+    let loc = format!("/v1/admin/invitations/{}", inv.id);
+    Ok(warp::reply::with_status(
+        warp::reply::with_header(
+            warp::reply::json(&json!({"invitation": inv})),
+            "Location",
+            loc,
+        ),
+        StatusCode::CREATED,
+    ))
+}
+
+#[instrument(skip(principal))]
+pub async fn revoke_invitation(
+    id: i64,
+    store: Store,
+    principal: Principal,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if store.delete_invitation(id).await? == 0 {
+        return Err(Error::RecordNotFound.into());
+    }
+    tracing::info!(invitation_id = id, by = ?principal.subject, "invitation revoked");
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({"message": "invitation successfully revoked"})),
+        StatusCode::OK,
+    ))
+}
+
+async fn user_grants(store: &Store, user_id: i64) -> Result<impl warp::Reply, warp::Rejection> {
+    let roles = store.roles_by_user(user_id).await?;
+    let permissions = store.permissions_by_user(user_id).await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({"roles": roles, "permissions": permissions})),
+        StatusCode::OK,
+    ))
+}
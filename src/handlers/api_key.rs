@@ -0,0 +1,102 @@
+use tracing::instrument;
+use warp::http::StatusCode;
+use serde_json::json;
+
+use crate::api_key::{ApiKey, ApiKeyJson, ServiceAccountJson};
+use crate::errors::Error;
+use crate::session::Principal;
+use crate::store::Store;
+use crate::validator::Validator;
+
+#[instrument(skip(principal))]
+pub async fn add_service_account(
+    input: ServiceAccountJson,
+    store: Store,
+    principal: Principal,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    input.validate().map_err(Error::Validation)?;
+
+    let ret = store.add_service_account(&input.name, principal.user_id()).await;
+    if let Err(Error::DuplicateName) = ret {
+        let mut v = Validator::new();
+        v.add_err("name", "a service account with this name already exists");
+        return Err(Error::Validation(v.get_err()).into());
+    }
+    let account = ret?;
+
+    let loc = format!("/v1/service-accounts/{}", account.id);
+    Ok(warp::reply::with_status(
+        warp::reply::with_header(
+            warp::reply::json(&json!({"service_account": account})),
+            "Location",
+            loc,
+        ),
+        StatusCode::CREATED,
+    ))
+}
+
+#[instrument(skip(_principal))]
+pub async fn list_service_accounts(
+    store: Store,
+    _principal: Principal,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let accounts = store.list_service_accounts().await?;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({"service_accounts": accounts})),
+        StatusCode::OK,
+    ))
+}
+
+#[instrument(skip(principal))]
+pub async fn add_api_key(
+    account_id: i64,
+    input: ApiKeyJson,
+    store: Store,
+    principal: Principal,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut v = Validator::new();
+    input.validate(&mut v, &principal.permissions);
+    if !v.valid() {
+        return Err(Error::Validation(v.get_err()).into());
+    }
+
+    let mut key = ApiKey::new(account_id, input.name, input.permissions, input.expiry);
+    store.add_api_key(&mut key).await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({"api_key": key})),
+        StatusCode::CREATED,
+    ))
+}
+
+#[instrument(skip(_principal))]
+pub async fn list_api_keys(
+    account_id: i64,
+    store: Store,
+    _principal: Principal,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let keys = store.api_keys_by_account(account_id).await?;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({"api_keys": keys})),
+        StatusCode::OK,
+    ))
+}
+
+#[instrument(skip(_principal))]
+pub async fn revoke_api_key(
+    account_id: i64,
+    key_id: i64,
+    store: Store,
+    _principal: Principal,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let count = store.delete_api_key(account_id, key_id).await?;
+    if count == 0 {
+        return Err(Error::RecordNotFound.into());
+    }
+
+    let msg = json!({"message": "api key successfully revoked"});
+    Ok(warp::reply::with_status(
+        warp::reply::json(&msg),
+        StatusCode::OK,
+    ))
+}
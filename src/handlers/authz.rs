@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+use tracing::instrument;
+use warp::http::StatusCode;
+use serde_json::json;
+
+use crate::errors::Error;
+use crate::policy::{principal_attributes, Policy};
+use crate::session::Principal;
+use crate::store::Store;
+use crate::validator::Validator;
+
+// evaluates the caller's own access, `resource` names what the action would
+// apply to, e.g. `?action=movies:update&resource=movie:42`
+#[instrument(skip(principal, policy))]
+pub async fn explain(
+    qs: HashMap<String, String>,
+    principal: Principal,
+    store: Store,
+    policy: Policy,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut v = Validator::new();
+    let action = qs.get("action").map(String::as_str).unwrap_or_default();
+    v.check(!action.is_empty(), "action", "must be provided");
+
+    let resource_id = match qs.get("resource").map(|r| r.split_once(':')) {
+        None => None,
+        Some(Some(("movie", id))) => id.parse::<i64>().map_err(|_| v.add_err("resource", "must be movie:<id>")).ok(),
+        Some(_) => {
+            v.add_err("resource", "must be movie:<id>");
+            None
+        },
+    };
+    if !v.valid() {
+        return Err(Error::Validation(v.get_err()).into());
+    }
+
+    let resource = match resource_id {
+        Some(id) => Some(store.get_movie(id).await?.attributes()),
+        None => None,
+    };
+    let decision = policy.evaluate(&principal, action, resource.as_ref());
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({
+            "decision": decision,
+            "principal": principal_attributes(&principal),
+            "resource": resource,
+        })),
+        StatusCode::OK,
+    ))
+}
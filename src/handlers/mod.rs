@@ -1,4 +1,10 @@
 pub mod movie;
 pub mod user;
 pub mod token;
+pub mod session;
+pub mod api_key;
+pub mod totp;
+pub mod oidc;
+pub mod admin;
+pub mod authz;
 mod password;
@@ -4,18 +4,24 @@ use serde_json::json;
 use std::collections::HashMap;
 
 use crate::store::Store;
-use crate::domain::movie::NewMovie;
+use crate::domain::movie::{Movie, NewMovie};
+use crate::policy::Policy;
+use crate::session::Principal;
 use crate::validator::Validator;
 use crate::errors::Error;
-use crate::filter::Filter;
+use crate::filter::{Filter, MetaData};
 
 
-#[instrument]
+#[instrument(skip(principal, policy))]
 pub async fn add_movie(
     store: Store,
     input: NewMovie,
+    principal: Principal,
+    policy: Policy,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let mut movie = input.try_into().map_err(Error::Validation)?;
+    let mut movie: Movie = input.try_into().map_err(Error::Validation)?;
+    movie.created_by = principal.user_id();
+    policy.authorize(&principal, "movies:create", Some(&movie.attributes()))?;
 
     store.add_movie(&mut movie).await?;  
 
@@ -29,24 +35,41 @@ pub async fn add_movie(
     )
 }
 
-#[instrument]
+#[instrument(skip(principal, policy))]
 pub async fn get_movie(
     id: i64,
     store: Store,
+    principal: Principal,
+    policy: Policy,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let movie = store.get_movie(id).await?;
+    policy.authorize(&principal, "movies:read", Some(&movie.attributes()))?;
     Ok( 
         warp::reply::with_status(warp::reply::json(&movie), StatusCode::OK)
     )
 }
 
-#[instrument]
+#[instrument(skip(principal, policy))]
 pub async fn update_movie(
     id: i64,
     input: NewMovie,
     store: Store,
+    principal: Principal,
+    policy: Policy,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let mut movie = store.get_movie(id).await?;
+    edit_movie(&mut movie, input, &principal, &policy)?;
+
+    store.update_movie(&mut movie).await?;  
+    Ok( 
+        warp::reply::with_status(warp::reply::json(&movie), StatusCode::OK)
+    )
+}
+
+// the caller has to be allowed to edit the movie both as it is and as it will be,
+// or an edit could move it out of the attributes their rules cover
+fn edit_movie(movie: &mut Movie, input: NewMovie, principal: &Principal, policy: &Policy) -> Result<(), Error> {
+    policy.authorize(principal, "movies:update", Some(&movie.attributes()))?;
 
     input.validate().map_err(Error::Validation)?;
 
@@ -63,17 +86,18 @@ pub async fn update_movie(
         movie.genres = genres;
     }
 
-    store.update_movie(&mut movie).await?;  
-    Ok( 
-        warp::reply::with_status(warp::reply::json(&movie), StatusCode::OK)
-    )
+    policy.authorize(principal, "movies:update", Some(&movie.attributes()))
 }
 
-#[instrument]
+#[instrument(skip(principal, policy))]
 pub async fn remove_movie(
     id: i64,
     store: Store,
+    principal: Principal,
+    policy: Policy,
 ) -> Result<impl warp::Reply, warp::Rejection> {
+    let movie = store.get_movie(id).await?;
+    policy.authorize(&principal, "movies:delete", Some(&movie.attributes()))?;
     let count = store.delete_movie(id).await?;
     if count == 0  {
         return Err(Error::RecordNotFound.into());
@@ -84,15 +108,28 @@ pub async fn remove_movie(
     )
 }
 
-#[instrument]
+#[instrument(skip(principal, policy))]
 pub async fn search_movie(
     qs: HashMap<String, String>,
     store: Store,
+    principal: Principal,
+    policy: Policy,
 )-> Result<impl warp::Reply, warp::Rejection> {
     let title =  qs.get("title").map(String::as_str).unwrap_or_default();
     let genres = qs.get("genres").map_or(vec![], |gs| gs.split(',').collect());
 
     let mut v = Validator::new(); 
+    let created_by = match qs.get("created_by").map(String::as_str) {
+        None => None,
+        Some("me") => {
+            v.check(principal.user_id().is_some(), "created_by", "requires a user account");
+            principal.user_id()
+        },
+        Some(_) => {
+            v.add_err("created_by", "must be me");
+            None
+        },
+    };
     let page = match qs.get("page") {
         None =>  1i64,
         Some(e) =>  {
@@ -120,7 +157,14 @@ pub async fn search_movie(
         return Err(Error::Validation(v.get_err()).into());
     }
 
-    let (meta, movies) = store.search_movie(title, genres, &filter).await?;
+    // Rules on a movie's attributes can't be put in the query, so when some apply every
+    // match is read and paged here instead. That costs a read of the whole result set,
+    // but the metadata then only counts what the caller may see.
+    let by_resource = policy.depends_on_resource(&principal, "movies:read");
+    let (mut meta, mut movies) = store.search_movie(title, genres, created_by, &filter, !by_resource).await?;
+    if by_resource {
+        (meta, movies) = visible_page(movies, &filter, &principal, &policy);
+    }
 
     Ok( 
         warp::reply::with_status(
@@ -130,4 +174,98 @@ pub async fn search_movie(
     )
 }
 
+// the same resource rules get_movie applies, evaluate rather than authorize so
+// a denied movie is left out instead of failing the search
+fn visible_page(mut movies: Vec<Movie>, filter: &Filter, principal: &Principal, policy: &Policy) -> (MetaData, Vec<Movie>) {
+    let found = movies.len();
+    movies.retain(|movie| policy.evaluate(principal, "movies:read", Some(&movie.attributes())).allowed);
+    if movies.len() < found {
+        tracing::debug!(hidden = found - movies.len(), "search results hidden by policy");
+    }
+
+    let meta = MetaData::calc(movies.len() as i64, filter.page, filter.page_size);
+    let page = movies.into_iter().skip(filter.offset() as usize).take(filter.limit() as usize).collect();
+    (meta, page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edit_movie, visible_page};
+    use crate::filter::{Filter, MetaData};
+    use crate::domain::movie::{Movie, NewMovie};
+    use crate::errors::Error;
+    use crate::policy::Policy;
+    use crate::session::{Principal, Subject};
+    use claims::{assert_matches, assert_ok};
+    use serde_json::json;
+
+    fn classics_editor() -> (Principal, Policy) {
+        let editor = Principal {
+            subject: Subject::User(2),
+            permissions: vec![],
+            roles: vec!["editor".to_owned()],
+            token_hash: None,
+            impersonator: None,
+        };
+        let policy = Policy::with_rules(json!([{
+            "name": "editors may update movies made before 1950",
+            "effect": "allow",
+            "actions": ["movies:update"],
+            "when": {"all": [
+                {"contains": [{"attr": "principal.roles"}, "editor"]},
+                {"lt": [{"attr": "resource.year"}, 1950]}
+            ]}
+        }]));
+        (editor, policy)
+    }
+
+    fn classic() -> Movie {
+        Movie { id: 1, title: "Metropolis".to_owned(), year: 1927, version: 1, ..Default::default() }
+    }
 
+    #[test]
+    fn edit_within_the_allowed_range() {
+        let (editor, policy) = classics_editor();
+        let mut movie = classic();
+        let input = NewMovie { title: None, year: Some(1926), runtime: None, genres: None };
+        assert_ok!(edit_movie(&mut movie, input, &editor, &policy));
+        assert_eq!(movie.year, 1926);
+    }
+
+    #[test]
+    fn edit_out_of_the_allowed_range_is_refused() {
+        let (editor, policy) = classics_editor();
+        let mut movie = classic();
+        let input = NewMovie { title: None, year: Some(2001), runtime: None, genres: None };
+        assert_matches!(edit_movie(&mut movie, input, &editor, &policy), Err(Error::Unauthorized));
+    }
+
+    #[test]
+    fn hidden_movies_are_left_out_of_pages_and_counts() {
+        let reader = Principal {
+            subject: Subject::User(3),
+            permissions: vec!["movies:read".to_owned()],
+            roles: vec![],
+            token_hash: None,
+            impersonator: None,
+        };
+        let policy = Policy::with_rules(json!([{
+            "name": "nobody sees restored classics",
+            "effect": "deny",
+            "actions": ["movies:read"],
+            "when": {"contains": [{"attr": "resource.genres"}, "restored"]}
+        }]));
+        let movies = (1..=5)
+            .map(|id| Movie {
+                id,
+                genres: if id % 2 == 0 { vec!["restored".to_owned()] } else { vec![] },
+                ..classic()
+            })
+            .collect();
+        let filter = Filter { page: 2, page_size: 2, sort: "id", sort_list: &["id"] };
+
+        let (meta, page) = visible_page(movies, &filter, &reader, &policy);
+        assert_eq!(page.iter().map(|m| m.id).collect::<Vec<_>>(), [5]);
+        assert_eq!(json!(meta), json!(MetaData::calc(3, 2, 2)));
+    }
+}
@@ -0,0 +1,262 @@
+use data_encoding::BASE64URL_NOPAD;
+use rand::RngCore;
+use redis::Client;
+use secrecy::Secret;
+use serde_json::json;
+use tracing::instrument;
+use warp::http::StatusCode;
+
+use crate::config::AuthConfig;
+use crate::errors::Error;
+use crate::jwt::JwtCodec;
+use crate::locale::Locale;
+use crate::oidc::{gen_state, CallbackJson, IdClaims, Oidc, PendingAuth};
+use crate::registration::Registration;
+use crate::session::ClientInfo;
+use crate::store::Store;
+use crate::token::{Token, SCOPE_ACTIVATION, SCOPE_AUTHENTICATION, SCOPE_MAGICLINK, SCOPE_PASSWORDRESET, SCOPE_REFRESH};
+use crate::user::User;
+use crate::user_pass::UserPass;
+use crate::validator::Validator;
+use crate::{Email, UserName};
+
+use super::password::gen_passwordhash;
+use super::token::{gen_session_tokens, mfa_challenge};
+use super::user::assign_default_role;
+
+#[instrument(skip(redis))]
+pub async fn authorize(
+    provider: String,
+    oidc: Oidc,
+    redis: Client,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let provider = oidc.provider(&provider).ok_or(Error::RecordNotFound)?;
+
+    let pending = PendingAuth::new(provider.name());
+    let state = gen_state();
+    let url = provider.authorization_url(&state, &pending).await?;
+    pending.save(&redis, &state).await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({"authorization_url": url, "state": state})),
+        StatusCode::OK,
+    ))
+}
+
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip_all, fields(provider = %input.provider))]
+pub async fn gen_oidc_token(
+    input: CallbackJson,
+    client: ClientInfo,
+    store: Store,
+    redis: Client,
+    config: AuthConfig,
+    jwt: Option<JwtCodec>,
+    registration: Registration,
+    oidc: Oidc,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut v = Validator::new();
+    v.check(!input.code.is_empty(), "code", "must be provided");
+    v.check(!input.state.is_empty(), "state", "must be provided");
+    if !v.valid() {
+        return Err(Error::Validation(v.get_err()).into());
+    }
+
+    let provider = oidc.provider(&input.provider).ok_or(Error::RecordNotFound)?;
+    let pending = PendingAuth::take(&redis, &input.state)
+        .await?
+        .filter(|pending| pending.provider == provider.name());
+    let Some(pending) = pending else {
+        v.add_err("state", "invalid or expired state");
+        return Err(Error::Validation(v.get_err()).into());
+    };
+
+    let claims = provider.exchange_code(&input.code, &pending).await?;
+    let user = match store.get_user_by_identity(provider.name(), &claims.sub).await {
+        Ok(user) => user,
+        Err(Error::RecordNotFound) => link_identity(&store, &config, &registration, provider.name(), &claims).await?,
+        Err(e) => return Err(e.into()),
+    };
+    if !user.activated {
+        return Err(Error::InactiveAccount.into());
+    }
+
+    if let Some(tok) = mfa_challenge(&store, &user, &client).await? {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"mfa_token": tok})),
+            StatusCode::ACCEPTED,
+        ));
+    }
+
+    let (access, refresh) =
+        gen_session_tokens(&store, &config, jwt.as_ref(), &user, Token::new_family(), &client).await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({"authentication_token": access, "refresh_token": refresh})),
+        StatusCode::CREATED,
+    ))
+}
+
+// first sign in through a provider: attach the identity to the account owning
+// the verified email, or open an account for it
+async fn link_identity(
+    store: &Store,
+    config: &AuthConfig,
+    registration: &Registration,
+    provider: &str,
+    claims: &IdClaims,
+) -> Result<User, Error> {
+    let email = match (&claims.email, claims.email_verified) {
+        (Some(email), true) => Email::parse(email.clone()).map_err(|_| Error::InvalidCredentials)?,
+        _ => {
+            tracing::warn!(provider, sub = %claims.sub, "provider did not assert a verified email");
+            return Err(Error::InvalidCredentials);
+        }
+    };
+
+    let user = match store.get_user_by_email(&email).await {
+        Ok(mut user) => {
+            if activate_on_link(&mut user, config).await? {
+                store.update_user(&mut user).await?;
+                // whoever registered the address can't have left a way back in
+                for scope in [SCOPE_ACTIVATION, SCOPE_PASSWORDRESET, SCOPE_MAGICLINK, SCOPE_AUTHENTICATION, SCOPE_REFRESH] {
+                    store.delete_token(scope, user.id).await?;
+                }
+                tracing::info!(user_id = user.id, provider, "activated pending account through oidc, password reset");
+            }
+            user
+        }
+        Err(Error::RecordNotFound) => {
+            registration.check(email.as_ref(), false)?;
+            create_user(store, config, email, claims.name.clone()).await?
+        }
+        Err(e) => return Err(e),
+    };
+
+    store.link_identity(user.id, provider, &claims.sub).await?;
+    tracing::info!(user_id = user.id, provider, "linked oidc identity");
+    Ok(user)
+}
+
+// the provider vouched for the address, which is all activation asks for, but an
+// account that was activated before and switched off by an admin stays off.
+// Anyone could have registered a pending account under the address, so its password
+// is replaced just like for accounts opened through a provider
+async fn activate_on_link(user: &mut User, config: &AuthConfig) -> Result<bool, Error> {
+    if user.activated {
+        return Ok(false);
+    }
+    if user.activated_at.is_some() {
+        return Err(Error::InactiveAccount);
+    }
+    user.password_hash = random_password_hash(config).await?;
+    user.activated = true;
+    Ok(true)
+}
+
+// the account can only be signed into through the provider until a password is reset
+async fn random_password_hash(config: &AuthConfig) -> Result<Secret<String>, Error> {
+    let mut buf = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut buf[..]);
+    gen_passwordhash(Secret::new(BASE64URL_NOPAD.encode(&buf)), config.argon2_params()?).await
+}
+
+async fn create_user(store: &Store, config: &AuthConfig, email: Email, name: Option<String>) -> Result<User, Error> {
+    let local_part = email.as_ref().split('@').next().unwrap_or_default().to_owned();
+    let name = name
+        .and_then(|name| UserName::parse(name).ok())
+        .or_else(|| UserName::parse(local_part).ok())
+        .unwrap_or_default();
+
+    let password_hash = random_password_hash(config).await?;
+
+    let mut user = User {
+        id: 0,
+        created_at: Default::default(),
+        name,
+        email,
+        password: UserPass(Secret::new(String::default())),
+        password_hash,
+        activated: true,
+        activated_at: None,
+        locale: Locale::default(),
+        version: 0,
+    };
+    store.add_user(&mut user).await?;
+    assign_default_role(store, config, user.id).await?;
+
+    Ok(user)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::activate_on_link;
+    use crate::config::AuthConfig;
+    use crate::errors::Error;
+    use crate::handlers::password::{gen_passwordhash, verify_passwordhash};
+    use crate::user::User;
+    use crate::user_pass::UserPass;
+    use crate::{Email, UserName};
+    use chrono::Utc;
+    use clap::Parser;
+    use claims::{assert_err, assert_matches, assert_ok, assert_ok_eq};
+    use secrecy::{ExposeSecret, Secret};
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        auth: AuthConfig,
+    }
+
+    fn config() -> AuthConfig {
+        Cli::parse_from(["greenlight", "--argon2-memory", "1024", "--argon2-iterations", "1"]).auth
+    }
+
+    fn user(activated: bool, activated_at: Option<chrono::DateTime<Utc>>) -> User {
+        User {
+            id: 1,
+            created_at: Utc::now(),
+            name: UserName::parse("fred".to_owned()).unwrap(),
+            email: Email::parse("fred@example.com".to_owned()).unwrap(),
+            password: UserPass(Secret::new(String::default())),
+            password_hash: Secret::new(String::default()),
+            activated,
+            activated_at,
+            locale: Default::default(),
+            version: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn never_activated_account_is_activated() {
+        let mut user = user(false, None);
+        assert_ok_eq!(activate_on_link(&mut user, &config()).await, true);
+        assert!(user.activated);
+    }
+
+    #[tokio::test]
+    async fn pre_registered_password_stops_working() {
+        let config = config();
+        let squatter = || Secret::new("squatters password".to_owned());
+        let mut user = user(false, None);
+        user.password_hash = gen_passwordhash(squatter(), config.argon2_params().unwrap()).await.unwrap();
+        assert_ok!(verify_passwordhash(user.password_hash.clone(), squatter()).await);
+
+        assert_ok_eq!(activate_on_link(&mut user, &config).await, true);
+        assert_err!(verify_passwordhash(user.password_hash.clone(), squatter()).await);
+    }
+
+    #[tokio::test]
+    async fn active_account_is_left_alone() {
+        let mut user = user(true, Some(Utc::now()));
+        assert_ok_eq!(activate_on_link(&mut user, &config()).await, false);
+        assert_eq!(user.password_hash.expose_secret(), "");
+    }
+
+    #[tokio::test]
+    async fn deactivated_account_stays_inactive() {
+        let mut user = user(false, Some(Utc::now()));
+        assert_matches!(activate_on_link(&mut user, &config()).await, Err(Error::InactiveAccount));
+        assert!(!user.activated);
+    }
+}
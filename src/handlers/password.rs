@@ -1,12 +1,15 @@
+use std::sync::OnceLock;
 use tokio::task::JoinHandle;
 use argon2::password_hash::SaltString;
 use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
 use secrecy::{ExposeSecret, Secret};
 use anyhow::Context;
 use crate::Error;
+use crate::breach_list::BreachList;
+use crate::validator::Validator;
 
-pub (super) async fn gen_passwordhash(password: Secret<String>)  -> Result<Secret<String>, Error> {
-    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+pub (super) async fn gen_passwordhash(password: Secret<String>, params: Params)  -> Result<Secret<String>, Error> {
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password, params))
         .await
         .context("Failed spawn hash task")?
         .context("Failed to hash password")?;
@@ -27,6 +30,104 @@ pub (super) async fn verify_passwordhash(
     Ok(())
 }
 
+// the current password counts as used too, a history of 0 allows reusing anything
+pub (super) async fn reuses_password(
+    current_hash: &Secret<String>,
+    mut history: Vec<Secret<String>>,
+    keep: u32,
+    password_candidate: &Secret<String>,
+) -> Result<bool, Error> {
+    if keep == 0 {
+        return Ok(false);
+    }
+    history.truncate(keep as usize);
+    history.push(current_hash.clone());
+    Ok(find_passwordhash(history, password_candidate).await?.is_some())
+}
+
+// checks the candidate against every hash at once, each on its own blocking thread,
+// and returns the position of the first hash it matches
+pub (super) async fn find_passwordhash(
+    hashes: Vec<Secret<String>>,
+    password_candidate: &Secret<String>,
+) -> Result<Option<usize>, Error> {
+    let checks = hashes
+        .into_iter()
+        .map(|hash| verify_passwordhash(hash, password_candidate.clone()));
+
+    for (i, ret) in futures_util::future::join_all(checks).await.into_iter().enumerate() {
+        match ret {
+            Ok(()) => return Ok(Some(i)),
+            Err(Error::InvalidCredentials) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(None)
+}
+
+// counted where the caller runs, so that tests running side by side don't see each other's
+#[cfg(test)]
+thread_local! {
+    pub (super) static DUMMY_VERIFICATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+// spends as long as verifying a real password would, so that a login for an unknown
+// email can't be told apart by its timing. The hash is made with the first params
+// seen, which are the configured ones for the lifetime of the process
+pub (super) async fn dummy_verify_passwordhash(password_candidate: Secret<String>, params: Params) {
+    static DUMMY_HASH: OnceLock<Secret<String>> = OnceLock::new();
+    #[cfg(test)]
+    DUMMY_VERIFICATIONS.with(|count| count.set(count.get() + 1));
+
+    let ret = spawn_blocking_with_tracing(move || {
+        if let Some(hash) = DUMMY_HASH.get() {
+            let _ = verify_password_hash(hash.clone(), password_candidate);
+            return Ok(());
+        }
+        // a failure isn't remembered, the next call tries again and hashes the candidate meanwhile
+        match compute_password_hash(Secret::new("not a real password".to_owned()), params.clone()) {
+            Ok(hash) => {
+                let hash = DUMMY_HASH.get_or_init(|| hash).clone();
+                let _ = verify_password_hash(hash, password_candidate);
+                Ok(())
+            }
+            Err(e) => compute_password_hash(password_candidate, params).map(|_| ()).map_err(|_| e),
+        }
+    })
+    .await;
+
+    match ret {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => tracing::error!(err = %e, "failed to compute dummy password hash"),
+        Err(e) => tracing::error!(err = %e, "dummy password verification failed"),
+    }
+}
+
+// true when the hash was computed with weaker costs than the current policy,
+// hashes that can't be parsed are left alone since they won't verify anyway
+pub (super) fn needs_rehash(password_hash: &Secret<String>, policy: &Params) -> bool {
+    let Ok(hash) = PasswordHash::new(password_hash.expose_secret()) else {
+        return false;
+    };
+    let Ok(params) = Params::try_from(&hash) else {
+        return false;
+    };
+
+    hash.algorithm != Algorithm::Argon2id.ident()
+        || hash.version.is_some_and(|v| v < Version::V0x13 as u32)
+        || params.m_cost() < policy.m_cost()
+        || params.t_cost() < policy.t_cost()
+        || params.p_cost() < policy.p_cost()
+}
+
+pub (super) fn check_breached(list: Option<&BreachList>, password: &Secret<String>) -> Result<(), Error> {
+    if list.is_some_and(|list| list.contains(password.expose_secret())) {
+        let mut v = Validator::new();
+        v.add_err("password", "password has appeared in a data breach");
+        return Err(Error::Validation(v.get_err()));
+    }
+    Ok(())
+}
 
 fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
 where
@@ -38,12 +139,12 @@ where
 }
 
 
-fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+fn compute_password_hash(password: Secret<String>, params: Params) -> Result<Secret<String>, anyhow::Error> {
     let salt = SaltString::generate(&mut rand::thread_rng());
     let password_hash = Argon2::new(
         Algorithm::Argon2id,
         Version::V0x13,
-        Params::new(15000, 2, 1, None).unwrap(),
+        params,
     )
     .hash_password(password.expose_secret().as_bytes(), &salt)?
     .to_string();
@@ -58,6 +159,8 @@ fn verify_password_hash(
     let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
         .context("Failed to parse hash in PHC string format.").map_err(Error::UnexpectedError)?;
 
+    // the costs, algorithm and version are taken from the hash itself, so hashes
+    // made under an older policy keep verifying until they are rehashed
     Argon2::default()
     .verify_password(
             password_candidate.expose_secret().as_bytes(),
@@ -67,3 +170,66 @@ fn verify_password_hash(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{compute_password_hash, find_passwordhash, needs_rehash, reuses_password};
+    use argon2::Params;
+    use secrecy::Secret;
+
+    fn hash_with(m_cost: u32, t_cost: u32) -> Secret<String> {
+        let params = Params::new(m_cost, t_cost, 1, None).unwrap();
+        compute_password_hash(Secret::new("pa55word".to_owned()), params).unwrap()
+    }
+
+    #[test]
+    fn hash_with_current_costs_is_kept() {
+        let policy = Params::new(1024, 2, 1, None).unwrap();
+        assert!(!needs_rehash(&hash_with(1024, 2), &policy));
+    }
+
+    #[test]
+    fn hash_with_weaker_costs_is_rehashed() {
+        let policy = Params::new(2048, 2, 1, None).unwrap();
+        assert!(needs_rehash(&hash_with(1024, 2), &policy));
+        assert!(needs_rehash(&hash_with(2048, 1), &policy));
+    }
+
+    fn hash_of(password: &str) -> Secret<String> {
+        compute_password_hash(Secret::new(password.to_owned()), Params::new(1024, 1, 1, None).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn current_password_is_rejected() {
+        let candidate = Secret::new("pa55word".to_owned());
+        assert!(reuses_password(&hash_of("pa55word"), vec![], 3, &candidate).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn only_the_kept_history_is_rejected() {
+        let history = vec![hash_of("second"), hash_of("first")];
+        let current = hash_of("third");
+        assert!(reuses_password(&current, history.clone(), 2, &Secret::new("first".to_owned())).await.unwrap());
+        assert!(!reuses_password(&current, history.clone(), 1, &Secret::new("first".to_owned())).await.unwrap());
+        assert!(!reuses_password(&current, history, 2, &Secret::new("fourth".to_owned())).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn matching_hash_is_found_by_position() {
+        let hashes = vec![hash_of("abcd-efgh"), hash_of("ijkl-mnop"), hash_of("qrst-uvwx")];
+        assert_eq!(find_passwordhash(hashes.clone(), &Secret::new("ijkl-mnop".to_owned())).await.unwrap(), Some(1));
+        assert_eq!(find_passwordhash(hashes, &Secret::new("yz23-4567".to_owned())).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn empty_history_setting_allows_any_password() {
+        let candidate = Secret::new("pa55word".to_owned());
+        assert!(!reuses_password(&hash_of("pa55word"), vec![hash_of("pa55word")], 0, &candidate).await.unwrap());
+    }
+
+    #[test]
+    fn unparsable_hash_is_left_alone() {
+        let policy = Params::new(1024, 2, 1, None).unwrap();
+        assert!(!needs_rehash(&Secret::new("not a phc string".to_owned()), &policy));
+    }
+}
@@ -0,0 +1,62 @@
+use tracing::instrument;
+use warp::http::StatusCode;
+use serde_json::json;
+
+use crate::config::{AuthConfig, AuthMode};
+use crate::errors::Error;
+use crate::session::{Principal, Session};
+use crate::token::{SCOPE_AUTHENTICATION, SCOPE_REFRESH};
+use crate::store::Store;
+use crate::validator::Validator;
+
+// signed access tokens are not stored, so in jwt mode a session is
+// tracked through its refresh token instead
+pub(super) fn session_scope(config: &AuthConfig) -> &'static str {
+    match config.auth_mode {
+        AuthMode::Opaque => SCOPE_AUTHENTICATION,
+        AuthMode::Jwt => SCOPE_REFRESH,
+    }
+}
+
+#[instrument(skip(principal))]
+pub async fn list_sessions(
+    principal: Principal,
+    store: Store,
+    config: AuthConfig,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let user_id = principal.user_id().ok_or(Error::Unauthorized)?;
+    let sessions = store
+        .sessions_by_user(user_id, session_scope(&config), principal.token_hash.as_deref())
+        .await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({"sessions": sessions})),
+        StatusCode::OK,
+    ))
+}
+
+#[instrument(skip(principal))]
+pub async fn revoke_session(
+    id: String,
+    principal: Principal,
+    store: Store,
+    config: AuthConfig,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let prefix = Session::parse_id(&id).map_err(|e| {
+        let mut v = Validator::new();
+        v.add_err("id", e);
+        Error::Validation(v.get_err())
+    })?;
+
+    let user_id = principal.user_id().ok_or(Error::Unauthorized)?;
+    let count = store.delete_session(user_id, session_scope(&config), &prefix).await?;
+    if count == 0 {
+        return Err(Error::RecordNotFound.into());
+    }
+
+    let msg = json!({"message": "session successfully revoked"});
+    Ok(warp::reply::with_status(
+        warp::reply::json(&msg),
+        StatusCode::OK,
+    ))
+}
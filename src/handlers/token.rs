@@ -1,18 +1,30 @@
 use chrono::Duration;
 use redis::Client;
+use secrecy::Secret;
 use serde_json::json;
 use warp::http::StatusCode;
 
-use crate::domain::token::Token;
+use crate::config::AuthConfig;
+use crate::domain::token::{RefreshJson, Token};
 use crate::domain::user::{EmailJson, LoginJson, LoginUser};
+use crate::domain::user::User;
 use crate::errors::Error;
+use crate::jwt::JwtCodec;
 use crate::store::Store;
-use crate::token::{SCOPE_ACTIVATION, SCOPE_AUTHENTICATION, SCOPE_PASSWORDRESET};
+use crate::token::{
+    SCOPE_ACTIVATION, SCOPE_AUTHENTICATION, SCOPE_MAGICLINK, SCOPE_MFA, SCOPE_PASSWORDRESET, SCOPE_REFRESH,
+};
+use crate::totp::MfaJson;
 use crate::validator::Validator;
 use crate::Email;
-use crate::mailer::{push_task, PasswordReset, TokenActivation};
+use crate::login_guard::{Failure, LoginGuard};
+use crate::mailer::{push_task, AccountLockout, DeclineReason, MagicLink, PasswordReset, RequestDeclined, TokenActivation};
+use crate::session::ClientInfo;
 
-use super::password::verify_passwordhash;
+use super::password::{dummy_verify_passwordhash, gen_passwordhash, needs_rehash, verify_passwordhash};
+use super::totp::check_second_factor;
+
+const MAGIC_LINK_TTL_MINUTES: i64 = 15;
 
 pub(super) async fn gen_token_and_save(
     store: Store,
@@ -25,31 +37,280 @@ pub(super) async fn gen_token_and_save(
     Ok(tok)
 }
 
+pub(super) async fn gen_session_tokens(
+    store: &Store,
+    config: &AuthConfig,
+    jwt: Option<&JwtCodec>,
+    user: &User,
+    family: Vec<u8>,
+    client: &ClientInfo,
+) -> Result<(Token, Token), Error> {
+    let access = match jwt {
+        Some(jwt) => {
+            let perms = store.permissions_by_user(user.id).await?;
+            let roles = store.roles_by_user(user.id).await?;
+            let (plain_text, expiry) = jwt.issue(user.id, user.activated, perms, roles)?;
+            Token::stateless(user.id, plain_text, expiry, SCOPE_AUTHENTICATION)
+        }
+        None => {
+            let access = Token::new(user.id, config.access_ttl(), SCOPE_AUTHENTICATION)
+                .with_client(client)
+                .with_family(family.clone());
+            store.save_token(&access).await?;
+            access
+        }
+    };
+
+    let refresh = Token::new(user.id, config.refresh_ttl(), SCOPE_REFRESH)
+        .with_client(client)
+        .with_family(family);
+    store.save_token(&refresh).await?;
+
+    Ok((access, refresh))
+}
+
+// lookups that fail because redis is unavailable let the attempt through,
+// locking everyone out of their accounts would be worse
+async fn ensure_not_locked(guard: &LoginGuard, email: &str, client: &ClientInfo) -> Result<(), Error> {
+    match guard.retry_after(email, client.ip.as_deref()).await {
+        Ok(Some(secs)) => Err(Error::TooManyRequests(secs)),
+        Ok(None) => Ok(()),
+        Err(e) => {
+            tracing::error!(err = %e, "login guard unavailable, allowing attempt");
+            Ok(())
+        }
+    }
+}
+
+async fn login_failed(guard: &LoginGuard, redis: &Client, email: &str, user: Option<&User>, client: &ClientInfo) {
+    match guard.record_failure(email, client.ip.as_deref()).await {
+        Ok(Some(Failure::LockedOut(secs))) => {
+            tracing::warn!(email, ip = ?client.ip, lockout_secs = secs, "account locked out after repeated failed logins");
+            let Some(user) = user else { return };
+            let task = match AccountLockout::new(secs).gen_task(user.email.clone().into()) {
+                Ok(task) => task,
+                Err(e) => {
+                    tracing::error!(err = %e, "failed to render lockout email");
+                    return;
+                }
+            };
+            if let Err(e) = push_task(redis, &task).await {
+                tracing::error!(err = %e, "failed to queue lockout email");
+            }
+        }
+        Ok(_) => {}
+        Err(e) => tracing::error!(err = %e, "failed to record login failure"),
+    }
+}
+
+async fn login_succeeded(guard: &LoginGuard, email: &str) {
+    if let Err(e) = guard.record_success(email).await {
+        tracing::error!(err = %e, "failed to reset login failures");
+    }
+}
+
+// unknown emails fail just like wrong passwords, with enumeration protection they
+// also take as long since a dummy hash gets verified
+async fn check_password(user: Option<&User>, password: Secret<String>, config: &AuthConfig) -> Result<(), Error> {
+    match user {
+        Some(user) => verify_passwordhash(user.password_hash.clone(), password).await,
+        None => {
+            if config.enumeration_protection {
+                dummy_verify_passwordhash(password, config.argon2_params()?).await;
+            }
+            Err(Error::InvalidCredentials)
+        }
+    }
+}
+
+// with enumeration protection the address gets told why nothing was sent instead of the
+// response, which looks the same and queues a mail just like a request that went through
+async fn mail_declined(redis: &Client, email: Email, requested: &'static str, reason: DeclineReason) -> Result<(), Error> {
+    let task = RequestDeclined::new(requested, reason)
+        .gen_task(email.into())
+        .map_err(Error::Render)?;
+    push_task(redis, &task).await.map_err(Error::UnexpectedError)
+}
+
+fn accepted(msg: serde_json::Value) -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(warp::reply::json(&msg), StatusCode::ACCEPTED)
+}
+
+// users with two-factor authentication get a short lived mfa token to complete
+// the login with instead of a session
+pub(super) async fn mfa_challenge(store: &Store, user: &User, client: &ClientInfo) -> Result<Option<Token>, Error> {
+    let mfa_enabled = match store.get_totp(user.id).await {
+        Ok(record) => record.enabled,
+        Err(Error::RecordNotFound) => false,
+        Err(e) => return Err(e),
+    };
+    if !mfa_enabled {
+        return Ok(None);
+    }
+
+    let tok = Token::new(user.id, chrono::Duration::minutes(5), SCOPE_MFA).with_client(client);
+    store.save_token(&tok).await?;
+    Ok(Some(tok))
+}
+
+// a failed rehash must not fail the login, the old hash still verifies
+async fn rehash_password(store: &Store, user: &mut User, password: Secret<String>, params: argon2::Params) {
+    match gen_passwordhash(password, params).await {
+        Ok(hash) => {
+            user.password_hash = hash;
+            match store.update_user(user).await {
+                Ok(()) => tracing::info!(user_id = user.id, "rehashed password with current argon2 params"),
+                Err(e) => tracing::error!(user_id = user.id, err = %e, "failed to save rehashed password"),
+            }
+        }
+        Err(e) => tracing::error!(user_id = user.id, err = %e, "failed to rehash password"),
+    }
+}
+
 pub async fn gen_auth_token(
     input: LoginJson,
+    client: ClientInfo,
     store: Store,
+    redis: Client,
+    config: AuthConfig,
+    jwt: Option<JwtCodec>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let login_user: LoginUser = input.try_into().map_err(Error::Validation)?;
-    let user = store.get_user_by_email(&login_user.email).await;
-    if let Err(Error::RecordNotFound) = user {
-        return Err(Error::InvalidCredentials.into());
-    } else if let Err(e) = user {
-        return Err(e.into());
+    let email = login_user.email.as_ref();
+    let guard = LoginGuard::new(redis.clone(), &config);
+    ensure_not_locked(&guard, email, &client).await?;
+
+    let user = match store.get_user_by_email(&login_user.email).await {
+        Ok(user) => Some(user),
+        Err(Error::RecordNotFound) => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    let password = login_user.password.0;
+    match check_password(user.as_ref(), password.clone(), &config).await {
+        Ok(()) => {}
+        Err(Error::InvalidCredentials) => {
+            login_failed(&guard, &redis, email, user.as_ref(), &client).await;
+            return Err(Error::InvalidCredentials.into());
+        }
+        Err(e) => return Err(e.into()),
     }
-    let user = user.unwrap();
-    let user_id = user.id;
+    let mut user = user.ok_or(Error::InvalidCredentials)?;
 
-    verify_passwordhash(user.password_hash, login_user.password.0).await?;
-    let tok = gen_token_and_save(
-        store,
-        user_id,
-        chrono::Duration::hours(24),
-        SCOPE_AUTHENTICATION,
-    )
-    .await?;
+    let params = config.argon2_params()?;
+    if needs_rehash(&user.password_hash, &params) {
+        rehash_password(&store, &mut user, password, params).await;
+    }
+
+    // the counters are only cleared once the second factor passed too
+    if let Some(tok) = mfa_challenge(&store, &user, &client).await? {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"mfa_token": tok})),
+            StatusCode::ACCEPTED,
+        ));
+    }
+
+    login_succeeded(&guard, email).await;
+
+    let (access, refresh) =
+        gen_session_tokens(&store, &config, jwt.as_ref(), &user, Token::new_family(), &client).await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({"authentication_token": access, "refresh_token": refresh})),
+        StatusCode::CREATED,
+    ))
+}
+
+pub async fn complete_mfa(
+    input: MfaJson,
+    client: ClientInfo,
+    store: Store,
+    redis: Client,
+    config: AuthConfig,
+    jwt: Option<JwtCodec>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut v = Validator::new();
+    Token::validate(&mut v, &input.mfa_token);
+    if !v.valid() {
+        return Err(Error::Validation(v.get_err()).into());
+    }
+
+    let user = store
+        .get_user_by_token(SCOPE_MFA, &input.mfa_token)
+        .await
+        .map_err(|e| match e {
+            Error::RecordNotFound => Error::InvalidAuthenticationToken,
+            _ => e,
+        })?;
+
+    let guard = LoginGuard::new(redis.clone(), &config);
+    let email = user.email.as_ref();
+    ensure_not_locked(&guard, email, &client).await?;
+
+    match check_second_factor(&store, user.id, input.code, input.recovery_code).await {
+        Ok(()) => login_succeeded(&guard, email).await,
+        Err(Error::InvalidCredentials) => {
+            login_failed(&guard, &redis, email, Some(&user), &client).await;
+            return Err(Error::InvalidCredentials.into());
+        }
+        Err(e) => return Err(e.into()),
+    }
+    store.delete_token(SCOPE_MFA, user.id).await?;
+
+    let (access, refresh) =
+        gen_session_tokens(&store, &config, jwt.as_ref(), &user, Token::new_family(), &client).await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({"authentication_token": access, "refresh_token": refresh})),
+        StatusCode::CREATED,
+    ))
+}
+
+pub async fn refresh_auth_token(
+    input: RefreshJson,
+    client: ClientInfo,
+    store: Store,
+    config: AuthConfig,
+    jwt: Option<JwtCodec>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut v = Validator::new();
+    Token::validate(&mut v, &input.refresh_token);
+    if !v.valid() {
+        return Err(Error::InvalidAuthenticationToken.into());
+    }
+
+    let user = store
+        .get_user_by_token(SCOPE_REFRESH, &input.refresh_token)
+        .await
+        .map_err(|e| match e {
+            Error::RecordNotFound => Error::InvalidAuthenticationToken,
+            _ => e,
+        })?;
+
+    let (family, reused) = store
+        .consume_refresh_token(&Token::gen_hash(&input.refresh_token))
+        .await
+        .map_err(|e| match e {
+            Error::RecordNotFound => Error::InvalidAuthenticationToken,
+            _ => e,
+        })?;
+
+    if reused {
+        tracing::warn!(user_id = user.id, "refresh token reuse detected, revoking token family");
+        store.delete_token_family(&family, None).await?;
+        return Err(Error::InvalidAuthenticationToken.into());
+    }
+
+    if !user.activated {
+        return Err(Error::InactiveAccount.into());
+    }
+
+    store.delete_token_family(&family, Some(SCOPE_AUTHENTICATION)).await?;
+    let (access, refresh) =
+        gen_session_tokens(&store, &config, jwt.as_ref(), &user, family, &client).await?;
 
     Ok(warp::reply::with_status(
-        warp::reply::json(&json!({"authentication_token": tok})),
+        warp::reply::json(&json!({"authentication_token": access, "refresh_token": refresh})),
         StatusCode::CREATED,
     ))
 }
@@ -58,12 +319,18 @@ pub async fn gen_reset_token(
     input: EmailJson,
     store: Store,
     redis: Client,
+    config: AuthConfig,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let email: Email = input.try_into().map_err(Error::Validation)?;
     let user = store.get_user_by_email(&email).await;
+    let msg = json!({"message": "an email will be sent to you containing password reset instructions"});
 
     let mut v = Validator::new();
     if let Err(Error::RecordNotFound) = user {
+        if config.enumeration_protection {
+            mail_declined(&redis, email, "password reset", DeclineReason::NoAccount).await?;
+            return Ok(accepted(msg));
+        }
         v.add_err("email", "no matching email address found");
         return Err(Error::Validation(v.get_err()).into());
     } else if let Err(e) = user {
@@ -71,6 +338,11 @@ pub async fn gen_reset_token(
     }
     let user = user.unwrap();
     if !user.activated {
+        if config.enumeration_protection {
+            tracing::info!(user_id = user.id, "password reset requested for an inactive account");
+            mail_declined(&redis, user.email, "password reset", DeclineReason::NotActivated).await?;
+            return Ok(accepted(msg));
+        }
         v.add_err("email", "user account must be activated");
         return Err(Error::Validation(v.get_err()).into());
     }
@@ -83,17 +355,13 @@ pub async fn gen_reset_token(
     )
     .await?;
 
-    let task =  PasswordReset::new(tok.plain_text)
+    let task =  PasswordReset::new(tok.plain_text, user.locale)
                 .gen_task(user.email.into())
                 .map_err(Error::Render)?;
 
     push_task(&redis, &task).await.map_err(Error::UnexpectedError)?; 
 
-    let msg = json!({"message": "an email will be sent to you containing password reset instructions"});
-    Ok(warp::reply::with_status(
-            warp::reply::json(&msg), StatusCode::ACCEPTED,
-        )
-    )
+    Ok(accepted(msg))
 }
 
 
@@ -101,12 +369,18 @@ pub async fn gen_activation_token(
     input: EmailJson,
     store: Store,
     redis: Client,
+    config: AuthConfig,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let email: Email = input.try_into().map_err(Error::Validation)?;
     let user = store.get_user_by_email(&email).await;
+    let msg = json!({"message": "an email will be sent to you containing activation instructions"});
 
     let mut v = Validator::new();
     if let Err(Error::RecordNotFound) = user {
+        if config.enumeration_protection {
+            mail_declined(&redis, email, "account activation", DeclineReason::NoAccount).await?;
+            return Ok(accepted(msg));
+        }
         v.add_err("email", "no matching email address found");
         return Err(Error::Validation(v.get_err()).into());
     } else if let Err(e) = user {
@@ -114,6 +388,11 @@ pub async fn gen_activation_token(
     }
     let user = user.unwrap();
     if user.activated {
+        if config.enumeration_protection {
+            tracing::info!(user_id = user.id, "activation requested for an active account");
+            mail_declined(&redis, user.email, "account activation", DeclineReason::AlreadyActivated).await?;
+            return Ok(accepted(msg));
+        }
         v.add_err("email", "user has already been activated");
         return Err(Error::Validation(v.get_err()).into());
     }
@@ -126,15 +405,181 @@ pub async fn gen_activation_token(
     )
     .await?;
 
-    let task =  TokenActivation::new(tok.plain_text)
+    let task =  TokenActivation::new(tok.plain_text, user.locale)
                 .gen_task(user.email.into())
                 .map_err(Error::Render)?;
 
     push_task(&redis, &task).await.map_err(Error::UnexpectedError)?; 
 
-    let msg = json!({"message": "an email will be sent to you containing activation instructions"});
-    Ok(warp::reply::with_status(
-            warp::reply::json(&msg), StatusCode::ACCEPTED,
-        )
+    Ok(accepted(msg))
+}
+
+pub async fn gen_magic_link(
+    input: EmailJson,
+    store: Store,
+    redis: Client,
+    config: AuthConfig,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let email: Email = input.try_into().map_err(Error::Validation)?;
+    let user = store.get_user_by_email(&email).await;
+    let msg = json!({"message": "an email will be sent to you containing a sign in link"});
+
+    let mut v = Validator::new();
+    if let Err(Error::RecordNotFound) = user {
+        if config.enumeration_protection {
+            mail_declined(&redis, email, "sign in link", DeclineReason::NoAccount).await?;
+            return Ok(accepted(msg));
+        }
+        v.add_err("email", "no matching email address found");
+        return Err(Error::Validation(v.get_err()).into());
+    } else if let Err(e) = user {
+        return Err(e.into());
+    }
+    let user = user.unwrap();
+    if !user.activated {
+        if config.enumeration_protection {
+            tracing::info!(user_id = user.id, "sign in link requested for an inactive account");
+            mail_declined(&redis, user.email, "sign in link", DeclineReason::NotActivated).await?;
+            return Ok(accepted(msg));
+        }
+        v.add_err("email", "user account must be activated");
+        return Err(Error::Validation(v.get_err()).into());
+    }
+
+    let tok = gen_token_and_save(
+        store,
+        user.id,
+        chrono::Duration::minutes(MAGIC_LINK_TTL_MINUTES),
+        SCOPE_MAGICLINK,
     )
+    .await?;
+
+    let task = MagicLink::new(tok.plain_text, MAGIC_LINK_TTL_MINUTES)
+                .gen_task(user.email.into())
+                .map_err(Error::Render)?;
+
+    push_task(&redis, &task).await.map_err(Error::UnexpectedError)?;
+
+    Ok(accepted(msg))
+}
+
+pub async fn exchange_magic_link(
+    input: Token,
+    client: ClientInfo,
+    store: Store,
+    config: AuthConfig,
+    jwt: Option<JwtCodec>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut v = Validator::new();
+    Token::validate(&mut v, &input.plain_text);
+    if !v.valid() {
+        return Err(Error::Validation(v.get_err()).into());
+    }
+
+    let user = store.get_user_by_token(SCOPE_MAGICLINK, &input.plain_text).await;
+    if let Err(Error::RecordNotFound) = user {
+        v.add_err("token", "invalid or expired sign in token");
+        return Err(Error::Validation(v.get_err()).into());
+    } else if let Err(e) = user {
+        return Err(e.into());
+    }
+    let user = user.unwrap();
+    // every outstanding link is spent, not just this one
+    store.delete_token(SCOPE_MAGICLINK, user.id).await?;
+
+    if let Some(tok) = mfa_challenge(&store, &user, &client).await? {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"mfa_token": tok})),
+            StatusCode::ACCEPTED,
+        ));
+    }
+
+    let (access, refresh) =
+        gen_session_tokens(&store, &config, jwt.as_ref(), &user, Token::new_family(), &client).await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({"authentication_token": access, "refresh_token": refresh})),
+        StatusCode::CREATED,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_password;
+    use crate::config::AuthConfig;
+    use crate::errors::{return_error, Error};
+    use crate::handlers::password::DUMMY_VERIFICATIONS;
+    use crate::user::User;
+    use crate::user_pass::UserPass;
+    use crate::{Email, UserName};
+    use argon2::password_hash::SaltString;
+    use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
+    use clap::Parser;
+    use claims::assert_matches;
+    use secrecy::Secret;
+    use warp::Reply;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        auth: AuthConfig,
+    }
+
+    fn config(enumeration_protection: bool) -> AuthConfig {
+        let mut config = Cli::parse_from(["greenlight", "--argon2-memory", "1024", "--argon2-iterations", "1"]).auth;
+        config.enumeration_protection = enumeration_protection;
+        config
+    }
+
+    fn user(password: &str) -> User {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, Params::new(1024, 1, 1, None).unwrap())
+            .hash_password(password.as_bytes(), &salt)
+            .unwrap()
+            .to_string();
+        User {
+            id: 1,
+            created_at: chrono::Utc::now(),
+            name: UserName::parse("fred".to_owned()).unwrap(),
+            email: Email::parse("fred@example.com".to_owned()).unwrap(),
+            password: UserPass(Secret::new(String::default())),
+            password_hash: Secret::new(hash),
+            activated: true,
+            activated_at: None,
+            locale: Default::default(),
+            version: 1,
+        }
+    }
+
+    async fn render(err: Error) -> (u16, Vec<u8>) {
+        let res = return_error(err.into()).await.unwrap().into_response();
+        let status = res.status().as_u16();
+        let body = warp::hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, body.to_vec())
+    }
+
+    #[tokio::test]
+    async fn unknown_email_answers_like_a_wrong_password() {
+        let config = config(true);
+        let password = || Secret::new("not the password".to_owned());
+
+        let unknown = check_password(None, password(), &config).await;
+        let wrong = check_password(Some(&user("pa55word")), password(), &config).await;
+        assert_matches!(unknown, Err(Error::InvalidCredentials));
+        assert_matches!(wrong, Err(Error::InvalidCredentials));
+        assert_eq!(render(unknown.unwrap_err()).await, render(wrong.unwrap_err()).await);
+    }
+
+    #[tokio::test]
+    async fn unknown_email_verifies_a_dummy_hash_only_when_protected() {
+        let password = || Secret::new("pa55word".to_owned());
+        let count = || DUMMY_VERIFICATIONS.with(|count| count.get());
+
+        let _ = check_password(None, password(), &config(true)).await;
+        assert_eq!(count(), 1);
+
+        let _ = check_password(Some(&user("pa55word")), password(), &config(true)).await;
+        let _ = check_password(None, password(), &config(false)).await;
+        assert_eq!(count(), 1);
+    }
 }
@@ -0,0 +1,134 @@
+use chrono::Utc;
+use secrecy::Secret;
+use serde_json::json;
+use tracing::instrument;
+use warp::http::StatusCode;
+
+use crate::config::AuthConfig;
+use crate::errors::Error;
+use crate::session::Principal;
+use crate::store::Store;
+use crate::totp::{gen_recovery_codes, Totp, TotpCodeJson};
+use crate::validator::Validator;
+use super::password::{find_passwordhash, gen_passwordhash};
+
+const ISSUER: &str = "Greenlight";
+
+#[instrument(skip(principal))]
+pub async fn enrol(
+    principal: Principal,
+    store: Store,
+    config: AuthConfig,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let user_id = principal.user_id().ok_or(Error::Unauthorized)?;
+    let user = store.get_user(user_id).await?;
+
+    match store.get_totp(user_id).await {
+        Ok(record) if record.enabled => {
+            let mut v = Validator::new();
+            v.add_err("totp", "two-factor authentication is already enabled");
+            return Err(Error::Validation(v.get_err()).into());
+        }
+        Ok(_) | Err(Error::RecordNotFound) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let totp = Totp::generate();
+    let recovery_codes = gen_recovery_codes();
+    let params = config.argon2_params()?;
+    let mut code_hashes = Vec::with_capacity(recovery_codes.len());
+    for code in &recovery_codes {
+        code_hashes.push(gen_passwordhash(Secret::new(code.clone()), params.clone()).await?);
+    }
+    store.save_totp_enrolment(user_id, totp.secret(), &code_hashes).await?;
+
+    let body = json!({
+        "totp": {
+            "secret": totp.secret_base32(),
+            "provisioning_uri": totp.provisioning_uri(ISSUER, user.email.as_ref()),
+            "recovery_codes": recovery_codes,
+        }
+    });
+    Ok(warp::reply::with_status(
+        warp::reply::json(&body),
+        StatusCode::CREATED,
+    ))
+}
+
+#[instrument(skip(principal, input))]
+pub async fn verify(
+    input: TotpCodeJson,
+    principal: Principal,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let user_id = principal.user_id().ok_or(Error::Unauthorized)?;
+
+    let mut v = Validator::new();
+    Totp::validate_code(&mut v, &input.code);
+    if !v.valid() {
+        return Err(Error::Validation(v.get_err()).into());
+    }
+
+    let record = store.get_totp(user_id).await;
+    if let Err(Error::RecordNotFound) = record {
+        v.add_err("code", "two-factor authentication has not been enrolled");
+        return Err(Error::Validation(v.get_err()).into());
+    }
+    let record = record?;
+    if record.enabled {
+        v.add_err("code", "two-factor authentication is already enabled");
+        return Err(Error::Validation(v.get_err()).into());
+    }
+
+    let accepted = match Totp::from_secret(record.secret).verify(&input.code, Utc::now().timestamp()) {
+        Some(step) => store.accept_totp_step(user_id, step).await?,
+        None => false,
+    };
+    if !accepted {
+        v.add_err("code", "invalid authentication code");
+        return Err(Error::Validation(v.get_err()).into());
+    }
+
+    let msg = json!({"message": "two-factor authentication successfully enabled"});
+    Ok(warp::reply::with_status(
+        warp::reply::json(&msg),
+        StatusCode::OK,
+    ))
+}
+
+// checks either a current totp code or an unused recovery code, recovery codes are burnt on use
+pub(super) async fn check_second_factor(
+    store: &Store,
+    user_id: i64,
+    code: Option<String>,
+    recovery_code: Option<String>,
+) -> Result<(), Error> {
+    match (code, recovery_code) {
+        (Some(code), None) => {
+            let record = store.get_totp(user_id).await?;
+            if !record.enabled {
+                return Err(Error::InvalidCredentials);
+            }
+            let step = Totp::from_secret(record.secret)
+                .verify(code.trim(), Utc::now().timestamp())
+                .ok_or(Error::InvalidCredentials)?;
+            if !store.accept_totp_step(user_id, step).await? {
+                return Err(Error::InvalidCredentials);
+            }
+            Ok(())
+        }
+        (None, Some(recovery_code)) => {
+            let candidate = Secret::new(recovery_code.trim().to_lowercase());
+            let (ids, hashes): (Vec<i64>, Vec<_>) = store.unused_recovery_codes(user_id).await?.into_iter().unzip();
+            match find_passwordhash(hashes, &candidate).await? {
+                Some(i) if store.use_recovery_code(ids[i]).await? => Ok(()),
+                _ => Err(Error::InvalidCredentials),
+            }
+        }
+        _ => {
+            let mut v = Validator::new();
+            v.add_err("code", "exactly one of code or recovery_code must be provided");
+            Err(Error::Validation(v.get_err()))
+        }
+    }
+}
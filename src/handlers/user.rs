@@ -8,19 +8,70 @@ use crate::validator::Validator;
 use crate::store::Store;
 use crate::mailer::{push_task, Welcome};
 use crate::user::{SignupJson, User};
+use crate::invitation::Invitation;
+use crate::locale::Locale;
 use crate::token::{SCOPE_ACTIVATION, SCOPE_PASSWORDRESET, Token};
-use super::password::gen_passwordhash;
+use crate::breach_list::BreachList;
+use crate::registration::Registration;
+use crate::config::AuthConfig;
+use super::password::{check_breached, gen_passwordhash, reuses_password};
 use super::token::gen_token_and_save;
 
+pub(super) async fn assign_default_role(store: &Store, config: &AuthConfig, user_id: i64) -> Result<(), Error> {
+    if store.assign_role(user_id, &config.default_role).await? == 0 {
+        tracing::warn!(user_id, role = %config.default_role, "default role does not exist, account has no permissions");
+    }
+    Ok(())
+}
+
+
+// an invitation is only good for the address it was sent to
+async fn find_invitation(store: &Store, token: &str, email: &str) -> Result<Invitation, Error> {
+    let mut v = Validator::new();
+    Invitation::validate(&mut v, token);
+    if !v.valid() {
+        return Err(Error::Validation(v.get_err()));
+    }
+
+    let inv = match store.get_invitation(&Token::gen_hash(token)).await {
+        Err(Error::RecordNotFound) => {
+            v.add_err("invitation", "invalid or expired invitation token");
+            return Err(Error::Validation(v.get_err()));
+        }
+        ret => ret?,
+    };
+    if !inv.email.eq_ignore_ascii_case(email) {
+        v.add_err("email", "must match the invited email address");
+        return Err(Error::Validation(v.get_err()));
+    }
+
+    Ok(inv)
+}
 
-#[instrument]
+#[instrument(skip(input))]
 pub async fn  register(
-    input: SignupJson,
+    mut input: SignupJson,
+    accept_language: Option<String>,
     store: Store,
     redis: Client,
+    config: AuthConfig,
+    breach_list: Option<BreachList>,
+    registration: Registration,
 ) -> Result<impl warp::Reply, warp::Rejection> {
+    let invitation = input.invitation.take();
+    let explicit_locale = input.locale.is_some();
     let mut user: User  = input.try_into().map_err(Error::Validation)?;
-    user.password_hash =  gen_passwordhash(user.password.clone().0).await?;
+    if !explicit_locale {
+        user.locale = accept_language.as_deref().and_then(Locale::negotiate).unwrap_or_default();
+    }
+    let invitation = match invitation {
+        Some(tok) => Some(find_invitation(&store, &tok, user.email.as_ref()).await?),
+        None => None,
+    };
+    registration.check(user.email.as_ref(), invitation.is_some())?;
+    check_breached(breach_list.as_ref(), &user.password.0)?;
+    user.password_hash =  gen_passwordhash(user.password.clone().0, config.argon2_params()?).await?;
+    user.activated = invitation.is_some();
 
     let ret = store.add_user(&mut user).await;
     if let Err(Error::DuplicateEmail) = ret {
@@ -30,10 +81,24 @@ pub async fn  register(
     }else if let Err(e)  =  ret  {
             return Err(e.into());
     }
-    store.grant_permissions_to_user(user.id, &vec!["movies:read".to_owned()]).await?; 
+
+    // invited accounts get exactly what the invitation carries and need no activation,
+    // emails are unique so the invitation can't be accepted twice
+    if let Some(inv) = invitation {
+        store.grant_permissions_to_user(user.id, &inv.permissions, None, inv.invited_by).await?;
+        store.delete_invitation(inv.id).await?;
+        tracing::info!(user_id = user.id, invitation_id = inv.id, "invitation accepted");
+
+        return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"user": &user})), StatusCode::CREATED,
+            )
+        );
+    }
+
+    assign_default_role(&store, &config, user.id).await?;
     let tok = gen_token_and_save(store, user.id, chrono::Duration::days(3),  SCOPE_ACTIVATION).await?;
 
-    let task =  Welcome::new(user.id, tok.plain_text)
+    let task =  Welcome::new(user.id, tok.plain_text, user.locale)
                 .gen_task(user.email.clone().into())
                 .map_err(Error::Render)?;
 
@@ -77,12 +142,15 @@ pub async fn  activate(
 pub async fn  password_update(
     input: ResetPass,
     store: Store,
+    config: AuthConfig,
+    breach_list: Option<BreachList>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let mut v = Validator::new(); 
     input.validate(&mut v);
     if !v.valid() {
             return Err(Error::Validation(v.get_err()).into());
     }
+    check_breached(breach_list.as_ref(), &input.password.0)?;
     let user = store.get_user_by_token(SCOPE_PASSWORDRESET, &input.token).await;
     if let Err(Error::RecordNotFound) = user {
             v.add_err("token", "invalid or expired password reset token");
@@ -91,8 +159,19 @@ pub async fn  password_update(
             return Err(e.into());
     }
     let mut user = user.unwrap();
-    user.password_hash =  gen_passwordhash(user.password.clone().0).await?;
-    store.update_user(&mut user).await?;
+
+    let keep = config.password_history;
+    let history = match keep {
+        0 => vec![],
+        _ => store.password_history(user.id, keep as i64).await?,
+    };
+    if reuses_password(&user.password_hash, history, keep, &input.password.0).await? {
+        v.add_err("password", "must not be the current password or one used recently");
+        return Err(Error::Validation(v.get_err()).into());
+    }
+
+    let password_hash =  gen_passwordhash(input.password.0, config.argon2_params()?).await?;
+    store.update_password(&mut user, password_hash, keep as i64).await?;
     store.delete_token(SCOPE_PASSWORDRESET, user.id).await?;
 
     Ok(warp::reply::with_status(
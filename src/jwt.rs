@@ -0,0 +1,209 @@
+use anyhow::Context;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::config::AuthConfig;
+use crate::Error;
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub act: bool,
+    pub perms: Vec<String>,
+    // absent from tokens issued before roles were added
+    #[serde(default)]
+    pub roles: Vec<String>,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+struct VerifyKey {
+    alg: Algorithm,
+    key: DecodingKey,
+}
+
+struct Inner {
+    kid: String,
+    alg: Algorithm,
+    encoding: EncodingKey,
+    keys: HashMap<String, VerifyKey>,
+    ttl: Duration,
+}
+
+// Signs access tokens with the first configured key and verifies them with
+// any configured key whose `kid` matches the token header, so a new key can be
+// prepended while tokens signed by the old one are still in flight.
+#[derive(Clone)]
+pub struct JwtCodec {
+    inner: Arc<Inner>,
+}
+
+impl std::fmt::Debug for JwtCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JwtCodec")
+            .field("kid", &self.inner.kid)
+            .field("alg", &self.inner.alg)
+            .field("keys", &self.inner.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl JwtCodec {
+    // key specs are `<kid>:hs256:<secret>` or `<kid>:eddsa:<private pem>:<public pem>`,
+    // the private pem path may be left empty for verification only keys
+    pub fn from_config(config: &AuthConfig) -> Result<Self, Error> {
+        let mut signing = None;
+        let mut keys = HashMap::new();
+
+        for (i, spec) in config.jwt_keys.iter().enumerate() {
+            let mut parts = spec.splitn(3, ':');
+            let (Some(kid), Some(alg), Some(material)) = (parts.next(), parts.next(), parts.next()) else {
+                return Err(Error::InvalidConfig(format!("malformed jwt key spec #{}", i)));
+            };
+
+            let (alg, encoding, decoding) = match alg {
+                "hs256" => (
+                    Algorithm::HS256,
+                    Some(EncodingKey::from_secret(material.as_bytes())),
+                    DecodingKey::from_secret(material.as_bytes()),
+                ),
+                "eddsa" => {
+                    let Some((private, public)) = material.split_once(':') else {
+                        return Err(Error::InvalidConfig(format!("jwt key {} needs a public pem path", kid)));
+                    };
+                    let encoding = if private.is_empty() {
+                        None
+                    } else {
+                        let pem = std::fs::read(private).context("failed to read jwt private key")?;
+                        Some(EncodingKey::from_ed_pem(&pem).context("failed to parse jwt private key")?)
+                    };
+                    let pem = std::fs::read(public).context("failed to read jwt public key")?;
+                    let decoding = DecodingKey::from_ed_pem(&pem).context("failed to parse jwt public key")?;
+                    (Algorithm::EdDSA, encoding, decoding)
+                }
+                _ => return Err(Error::InvalidConfig(format!("unsupported jwt algorithm {}", alg))),
+            };
+
+            if i == 0 {
+                let Some(encoding) = encoding else {
+                    return Err(Error::InvalidConfig(format!("jwt signing key {} has no private part", kid)));
+                };
+                signing = Some((kid.to_owned(), alg, encoding));
+            }
+            keys.insert(kid.to_owned(), VerifyKey { alg, key: decoding });
+        }
+
+        let Some((kid, alg, encoding)) = signing else {
+            return Err(Error::InvalidConfig("jwt auth mode requires at least one jwt key".to_owned()));
+        };
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                kid,
+                alg,
+                encoding,
+                keys,
+                ttl: config.jwt_ttl(),
+            }),
+        })
+    }
+
+    pub fn issue(
+        &self,
+        user_id: i64,
+        activated: bool,
+        perms: Vec<String>,
+        roles: Vec<String>,
+    ) -> Result<(String, DateTime<Utc>), Error> {
+        let now = Utc::now();
+        let expiry = now + self.inner.ttl;
+        let claims = Claims {
+            sub: user_id.to_string(),
+            act: activated,
+            perms,
+            roles,
+            iat: now.timestamp(),
+            exp: expiry.timestamp(),
+        };
+
+        let mut header = Header::new(self.inner.alg);
+        header.kid = Some(self.inner.kid.clone());
+        let token = jsonwebtoken::encode(&header, &claims, &self.inner.encoding)
+            .context("failed to sign access token")?;
+
+        Ok((token, Utc.timestamp_opt(claims.exp, 0).single().unwrap_or(expiry)))
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Claims, Error> {
+        let header = jsonwebtoken::decode_header(token).map_err(|_| Error::InvalidAuthenticationToken)?;
+        let key = header
+            .kid
+            .and_then(|kid| self.inner.keys.get(&kid))
+            .ok_or(Error::InvalidAuthenticationToken)?;
+
+        let validation = Validation::new(key.alg);
+        let data = jsonwebtoken::decode::<Claims>(token, &key.key, &validation).map_err(|e| {
+            tracing::debug!(err = %e, "jwt verification failed");
+            Error::InvalidAuthenticationToken
+        })?;
+
+        Ok(data.claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JwtCodec;
+    use crate::config::{AuthConfig, AuthMode};
+    use clap::Parser;
+    use claims::{assert_err, assert_ok};
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        auth: AuthConfig,
+    }
+
+    fn config(keys: &[&str]) -> AuthConfig {
+        let mut config = Cli::parse_from(["greenlight", "--jwt-token-ttl", "60"]).auth;
+        config.auth_mode = AuthMode::Jwt;
+        config.jwt_keys = keys.iter().map(|k| k.to_string().into()).collect();
+        config
+    }
+
+    #[test]
+    fn issued_token_is_verified_with_its_claims() {
+        let codec = JwtCodec::from_config(&config(&["k1:hs256:secret"])).unwrap();
+        let (token, _) = codec.issue(42, true, vec!["movies:read".to_owned()], vec!["viewer".to_owned()]).unwrap();
+        let claims = codec.verify(&token).unwrap();
+        assert_eq!(claims.sub, "42");
+        assert!(claims.act);
+        assert_eq!(claims.perms, vec!["movies:read".to_owned()]);
+        assert_eq!(claims.roles, vec!["viewer".to_owned()]);
+    }
+
+    #[test]
+    fn token_signed_with_rotated_out_key_is_still_accepted() {
+        let old = JwtCodec::from_config(&config(&["k1:hs256:old-secret"])).unwrap();
+        let (token, _) = old.issue(1, true, vec![], vec![]).unwrap();
+        let rotated = JwtCodec::from_config(&config(&["k2:hs256:new-secret", "k1:hs256:old-secret"])).unwrap();
+        assert_ok!(rotated.verify(&token));
+    }
+
+    #[test]
+    fn token_with_unknown_kid_is_rejected() {
+        let other = JwtCodec::from_config(&config(&["k9:hs256:secret"])).unwrap();
+        let (token, _) = other.issue(1, true, vec![], vec![]).unwrap();
+        let codec = JwtCodec::from_config(&config(&["k1:hs256:secret"])).unwrap();
+        assert_err!(codec.verify(&token));
+    }
+
+    #[test]
+    fn missing_or_malformed_keys_are_rejected() {
+        assert_err!(JwtCodec::from_config(&config(&[])));
+        assert_err!(JwtCodec::from_config(&config(&["k1:hs256"])));
+        assert_err!(JwtCodec::from_config(&config(&["k1:rs256:secret"])));
+    }
+}
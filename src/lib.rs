@@ -6,28 +6,64 @@ mod validator;
 mod route;
 mod handlers;
 mod mailer;
+mod jwt;
+mod login_guard;
+mod rate_limit;
+mod breach_list;
+mod oidc;
+mod sweeper;
+mod policy;
+mod auth_cache;
+mod registration;
 
 pub use errors::Error;
 pub use config::Config;
 pub use mailer::run_mail_worker;
+pub use sweeper::run_sweeper;
 use domain::*;
 use route::build_routes;
 use store::Store;
 use mailer::Mailer;
+use sweeper::Sweeper;
+use jwt::JwtCodec;
+use breach_list::BreachList;
+use oidc::Oidc;
+use policy::Policy;
+use auth_cache::AuthCache;
+use registration::Registration;
+use config::AuthMode;
 
 
 use anyhow::Context;
 use warp::{Filter, Reply};
 
-pub fn build(config: config::Config) -> Result<(Mailer, warp::Server<impl Filter<Extract = impl Reply> + Clone>) , Error> {
-    let store = Store::new(&config.pg)?;
-   
+pub fn build(config: config::Config) -> Result<(Mailer, Sweeper, warp::Server<impl Filter<Extract = impl Reply> + Clone>) , Error> {
     let redis = redis::Client::open(config.redis_url)
         .context("failed to parse redis_url")
         .map_err(Error::UnexpectedError)?;
 
+    let store = Store::new(&config.pg, AuthCache::new(redis.clone(), config.auth.auth_cache_ttl))?;
+
+    // fail at startup rather than on the first signup
+    config.auth.argon2_params()?;
+
+    let jwt = match config.auth.auth_mode {
+        AuthMode::Jwt => Some(JwtCodec::from_config(&config.auth)?),
+        AuthMode::Opaque => None,
+    };
+
+    let breach_list = config.auth.breached_password_file
+        .as_deref()
+        .map(BreachList::open)
+        .transpose()?;
+
+    let registration = Registration::from_config(&config.auth)?;
+    let oidc = Oidc::from_config(&config.oidc)?;
+    let policy = Policy::load(config.auth.policy_file.as_deref())?;
+
     let mailer =  Mailer::new(config.mail, redis.clone());
-    let routes =  build_routes(store, redis);     //.await;
-    Ok((mailer, warp::serve(routes)))            
+    let sweeper = Sweeper::new(store.clone(), &config.sweep);
+    let routes =  build_routes(store, redis, config.auth, config.rate_limit, jwt, breach_list, registration, oidc, policy);     //.await;
+    Ok((mailer, sweeper, warp::serve(routes)))            
 }
 
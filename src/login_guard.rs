@@ -0,0 +1,163 @@
+use anyhow::Context;
+use redis::Client;
+
+use crate::config::AuthConfig;
+use crate::Error;
+
+// failures an account gets for free before every further attempt is delayed
+const FREE_ATTEMPTS: u64 = 3;
+
+// Tracks failed logins per account and per client ip in redis. Accounts are
+// delayed exponentially after a few failures and locked out once the configured
+// limit is reached, ips are only locked out since many users may share one.
+pub struct LoginGuard {
+    redis: Client,
+    max_attempts: u64,
+    ip_max_attempts: u64,
+    lockout_secs: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Failure {
+    Delayed(u64),
+    LockedOut(u64),
+}
+
+impl LoginGuard {
+    pub fn new(redis: Client, config: &AuthConfig) -> Self {
+        Self {
+            redis,
+            max_attempts: config.login_max_attempts.into(),
+            ip_max_attempts: config.login_ip_max_attempts.into(),
+            lockout_secs: config.login_lockout.as_secs().max(1),
+        }
+    }
+
+    // returns the seconds left before the account or the ip may try again
+    pub async fn retry_after(&self, email: &str, ip: Option<&str>) -> Result<Option<u64>, Error> {
+        let mut conn = self.conn().await?;
+        let mut pipe = redis::pipe();
+        pipe.cmd("TTL").arg(lock_key("acct", email));
+        if let Some(ip) = ip {
+            pipe.cmd("TTL").arg(lock_key("ip", ip));
+        }
+        let ttls: Vec<i64> = pipe
+            .query_async(&mut conn)
+            .await
+            .context("failed to read login locks")
+            .map_err(Error::UnexpectedError)?;
+
+        Ok(ttls.into_iter().filter(|&t| t > 0).max().map(|t| t as u64))
+    }
+
+    pub async fn record_failure(&self, email: &str, ip: Option<&str>) -> Result<Option<Failure>, Error> {
+        let mut conn = self.conn().await?;
+
+        let failures = self.incr(&mut conn, &fail_key("acct", email)).await?;
+        let failure = account_failure(failures, self.max_attempts, self.lockout_secs);
+        if let Some(Failure::Delayed(secs) | Failure::LockedOut(secs)) = failure {
+            self.lock(&mut conn, &lock_key("acct", email), secs).await?;
+        }
+
+        if let Some(ip) = ip {
+            let failures = self.incr(&mut conn, &fail_key("ip", ip)).await?;
+            if failures >= self.ip_max_attempts {
+                tracing::warn!(ip, failures, "client ip locked out after repeated failed logins");
+                self.lock(&mut conn, &lock_key("ip", ip), self.lockout_secs).await?;
+            }
+        }
+
+        Ok(failure)
+    }
+
+    pub async fn record_success(&self, email: &str) -> Result<(), Error> {
+        let mut conn = self.conn().await?;
+        redis::cmd("DEL")
+            .arg(fail_key("acct", email))
+            .arg(lock_key("acct", email))
+            .query_async::<_, ()>(&mut conn)
+            .await
+            .context("failed to reset login failures")
+            .map_err(Error::UnexpectedError)
+    }
+
+    async fn conn(&self) -> Result<redis::aio::Connection, Error> {
+        self.redis
+            .get_async_connection()
+            .await
+            .context("failed to get redis conn in login guard")
+            .map_err(Error::UnexpectedError)
+    }
+
+    // the counter window starts at the first failure and outlives a full lockout
+    async fn incr(&self, conn: &mut redis::aio::Connection, key: &str) -> Result<u64, Error> {
+        let (failures,): (u64,) = redis::pipe()
+            .atomic()
+            .cmd("SET").arg(key).arg(0).arg("EX").arg(self.lockout_secs * 2).arg("NX").ignore()
+            .cmd("INCR").arg(key)
+            .query_async(conn)
+            .await
+            .context("failed to count login failure")
+            .map_err(Error::UnexpectedError)?;
+        Ok(failures)
+    }
+
+    async fn lock(&self, conn: &mut redis::aio::Connection, key: &str, secs: u64) -> Result<(), Error> {
+        redis::cmd("SET")
+            .arg(key)
+            .arg(1)
+            .arg("EX")
+            .arg(secs)
+            .query_async::<_, ()>(conn)
+            .await
+            .context("failed to set login lock")
+            .map_err(Error::UnexpectedError)
+    }
+}
+
+fn account_failure(failures: u64, max_attempts: u64, lockout_secs: u64) -> Option<Failure> {
+    if failures >= max_attempts {
+        Some(Failure::LockedOut(lockout_secs))
+    } else if failures > FREE_ATTEMPTS {
+        let exp = (failures - FREE_ATTEMPTS).min(32) as u32;
+        Some(Failure::Delayed(2u64.pow(exp).min(lockout_secs)))
+    } else {
+        None
+    }
+}
+
+fn fail_key(kind: &str, id: &str) -> String {
+    format!("login_fail:{}:{}", kind, id.to_lowercase())
+}
+
+fn lock_key(kind: &str, id: &str) -> String {
+    format!("login_lock:{}:{}", kind, id.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{account_failure, Failure};
+    use claims::assert_none;
+
+    #[test]
+    fn first_failures_are_not_delayed() {
+        assert_none!(account_failure(3, 10, 900));
+    }
+
+    #[test]
+    fn delay_doubles_after_free_attempts() {
+        assert_eq!(account_failure(4, 10, 900), Some(Failure::Delayed(2)));
+        assert_eq!(account_failure(5, 10, 900), Some(Failure::Delayed(4)));
+        assert_eq!(account_failure(9, 10, 900), Some(Failure::Delayed(64)));
+    }
+
+    #[test]
+    fn delay_is_capped_by_lockout() {
+        assert_eq!(account_failure(40, 100, 900), Some(Failure::Delayed(900)));
+    }
+
+    #[test]
+    fn reaching_the_limit_locks_the_account() {
+        assert_eq!(account_failure(10, 10, 900), Some(Failure::LockedOut(900)));
+    }
+}
@@ -7,6 +7,7 @@ use std::time::Duration;
 use tracing::instrument;
 
 use crate::config::MailConfig;
+use crate::locale::Locale;
 
 #[derive(Clone)]
 pub struct Mailer {
@@ -119,7 +120,7 @@ pub async fn push_task(client: &Client, task: &MailTask) -> Result<(), anyhow::E
     redis::cmd("LPUSH")
         .arg("mail_queue")
         .arg(task_msg)
-        .query_async(&mut conn)
+        .query_async::<_, ()>(&mut conn)
         .await
         .context("push mail task failed")?;
 
@@ -158,6 +159,7 @@ trait MutablePart: askama::Template + Sized {
 #[template(path = "user_welcome.tmpl", escape = "html")]
 pub struct Welcome {
     part: MailPart,
+    locale: Locale,
     user_id: i64,
     activation_token: String,
 }
@@ -169,9 +171,10 @@ impl MutablePart for Welcome {
 }
 
 impl Welcome {
-    pub fn new(user_id: i64, activation_token: String) -> Self {
+    pub fn new(user_id: i64, activation_token: String, locale: Locale) -> Self {
         Self {
             part:  MailPart::default(),
+            locale,
             user_id,
             activation_token,
         }
@@ -186,6 +189,7 @@ impl Welcome {
 #[template(path = "password_reset.tmpl", escape = "html")]
 pub struct PasswordReset {
     part: MailPart,
+    locale: Locale,
     reset_token: String,
 }
 
@@ -196,9 +200,10 @@ impl MutablePart for PasswordReset {
 }
 
 impl PasswordReset {
-    pub fn new(reset_token: String) -> Self {
+    pub fn new(reset_token: String, locale: Locale) -> Self {
         Self {
             part: MailPart::default(),
+            locale,
             reset_token,
         }
     }
@@ -208,10 +213,39 @@ impl PasswordReset {
     }
 }
 
+#[derive(Template, Default)]
+#[template(path = "magic_link.tmpl", escape = "html")]
+pub struct MagicLink {
+    part: MailPart,
+    login_token: String,
+    minutes: i64,
+}
+
+impl MutablePart for MagicLink {
+    fn part(&mut self) -> &mut MailPart {
+        &mut self.part
+    }
+}
+
+impl MagicLink {
+    pub fn new(login_token: String, minutes: i64) -> Self {
+        Self {
+            part: MailPart::default(),
+            login_token,
+            minutes,
+        }
+    }
+
+    pub fn gen_task(self, recipient: String) -> Result<MailTask, askama::Error> {
+        <Self as MutablePart>::gen_task(self, recipient)
+    }
+}
+
 #[derive(Template, Default)]
 #[template(path = "token_activation.tmpl", escape = "html")]
 pub struct TokenActivation {
     part: MailPart,
+    locale: Locale,
     activation_token: String,
 }
 
@@ -222,9 +256,10 @@ impl MutablePart for TokenActivation {
 }
 
 impl TokenActivation {
-    pub fn new(activation_token: String) -> Self {
+    pub fn new(activation_token: String, locale: Locale) -> Self {
         Self{
             part: MailPart::default(),
+            locale,
             activation_token,
         }
     }
@@ -234,3 +269,133 @@ impl TokenActivation {
     }
 }
 
+
+#[derive(Template, Default)]
+#[template(path = "account_lockout.tmpl", escape = "html")]
+pub struct AccountLockout {
+    part: MailPart,
+    minutes: u64,
+}
+
+impl MutablePart for AccountLockout {
+    fn part(&mut self) -> &mut MailPart {
+        &mut self.part
+    }
+}
+
+impl AccountLockout {
+    pub fn new(lockout_secs: u64) -> Self {
+        Self {
+            part: MailPart::default(),
+            minutes: lockout_secs.div_ceil(60),
+        }
+    }
+
+    pub fn gen_task(self, recipient: String) -> Result<MailTask, askama::Error> {
+        <Self as MutablePart>::gen_task(self, recipient)
+    }
+}
+
+#[derive(Template, Default)]
+#[template(path = "invitation.tmpl", escape = "html")]
+pub struct Invite {
+    part: MailPart,
+    email: String,
+    invitation_token: String,
+    expiry: String,
+}
+
+impl MutablePart for Invite {
+    fn part(&mut self) -> &mut MailPart {
+        &mut self.part
+    }
+}
+
+impl Invite {
+    pub fn new(email: String, invitation_token: String, expiry: chrono::DateTime<chrono::Utc>) -> Self {
+        Self {
+            part: MailPart::default(),
+            email,
+            invitation_token,
+            expiry: expiry.format("%Y-%m-%d %H:%M UTC").to_string(),
+        }
+    }
+
+    pub fn gen_task(self, recipient: String) -> Result<MailTask, askama::Error> {
+        <Self as MutablePart>::gen_task(self, recipient)
+    }
+}
+
+#[derive(Template, Default)]
+#[template(path = "impersonation_notice.tmpl", escape = "html")]
+pub struct ImpersonationNotice {
+    part: MailPart,
+    admin_name: String,
+    started: String,
+    expiry: String,
+}
+
+impl MutablePart for ImpersonationNotice {
+    fn part(&mut self) -> &mut MailPart {
+        &mut self.part
+    }
+}
+
+impl ImpersonationNotice {
+    pub fn new(admin_name: String, started: chrono::DateTime<chrono::Utc>, expiry: chrono::DateTime<chrono::Utc>) -> Self {
+        Self {
+            part: MailPart::default(),
+            admin_name,
+            started: started.format("%Y-%m-%d %H:%M UTC").to_string(),
+            expiry: expiry.format("%Y-%m-%d %H:%M UTC").to_string(),
+        }
+    }
+
+    pub fn gen_task(self, recipient: String) -> Result<MailTask, askama::Error> {
+        <Self as MutablePart>::gen_task(self, recipient)
+    }
+}
+
+// why an emailed token wasn't sent, with enumeration protection the address is told
+// instead of the response
+#[derive(Copy, Clone, Debug, Default)]
+pub enum DeclineReason {
+    #[default]
+    NoAccount,
+    NotActivated,
+    AlreadyActivated,
+}
+
+#[derive(Template, Default)]
+#[template(path = "request_declined.tmpl", escape = "html")]
+pub struct RequestDeclined {
+    part: MailPart,
+    article: &'static str,
+    requested: &'static str,
+    reason: DeclineReason,
+}
+
+impl MutablePart for RequestDeclined {
+    fn part(&mut self) -> &mut MailPart {
+        &mut self.part
+    }
+}
+
+impl RequestDeclined {
+    pub fn new(requested: &'static str, reason: DeclineReason) -> Self {
+        let article = match requested.starts_with(['a', 'e', 'i', 'o', 'u']) {
+            true => "an",
+            false => "a",
+        };
+        Self {
+            part: MailPart::default(),
+            article,
+            requested,
+            reason,
+        }
+    }
+
+    pub fn gen_task(self, recipient: String) -> Result<MailTask, askama::Error> {
+        <Self as MutablePart>::gen_task(self, recipient)
+    }
+}
@@ -3,7 +3,7 @@ use anyhow::Context;
 use tokio::task::JoinError;
 use std::fmt::{Debug, Display};
 
-use greenlight::{Error, Config, build, run_mail_worker};
+use greenlight::{Error, Config, build, run_mail_worker, run_sweeper};
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
@@ -27,14 +27,16 @@ async fn main() -> Result<(), Error> {
         .map_err(Error::UnexpectedError)?;
     
 
-    let (mailer, api_server) =  build(config)?;
+    let (mailer, sweeper, api_server) =  build(config)?;
 
     let mail_task =  tokio::spawn(run_mail_worker(mailer));
+    let sweep_task =  tokio::spawn(run_sweeper(sweeper));
     let api_task =  tokio::spawn(api_server.run(sock_addr));
 
     tokio::select! {
         o = api_task  => report_exit("api worker",    Ok(o)),
         o = mail_task =>  report_exit("mail worker", o),
+        o = sweep_task =>  report_exit("sweeper", o),
     };
     
     Ok(())
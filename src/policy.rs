@@ -0,0 +1,388 @@
+use anyhow::Context;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::role::grants;
+use crate::session::{Principal, Subject};
+use crate::Error;
+
+// the rules the flat permission codes used to stand for, see policies/default.json
+const DEFAULT_POLICY: &str = include_str!("../policies/default.json");
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Effect {
+    Allow,
+    Deny,
+}
+
+// written as `{"all": [...]}`, `{"has": "movies:read"}`, `{"lt": [{"attr": "resource.year"}, 1950]}`
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+    Not(Box<Condition>),
+    // the principal holds the permission code, `:any` covering `:own`
+    Has(String),
+    Eq(Operand, Operand),
+    Ne(Operand, Operand),
+    Lt(Operand, Operand),
+    Le(Operand, Operand),
+    Gt(Operand, Operand),
+    Ge(Operand, Operand),
+    Contains(Operand, Operand),
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(untagged)]
+pub enum Operand {
+    Attr { attr: String },
+    Value(Value),
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Rule {
+    pub name: String,
+    pub effect: Effect,
+    // action patterns, wildcarded the same way as role permissions
+    pub actions: Vec<String>,
+    #[serde(default)]
+    pub when: Option<Condition>,
+}
+
+#[derive(serde::Deserialize)]
+struct PolicyFile {
+    #[serde(default = "keep_defaults")]
+    include_defaults: bool,
+    rules: Vec<Rule>,
+}
+
+fn keep_defaults() -> bool {
+    true
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct RuleTrace {
+    pub rule: String,
+    pub effect: Effect,
+    // null when the rule depends on resource attributes that were not supplied
+    pub matched: Option<bool>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Decision {
+    pub action: String,
+    pub allowed: bool,
+    pub reason: String,
+    pub rules: Vec<RuleTrace>,
+}
+
+// A deny that matches wins over any allow, and nothing is allowed unless a rule
+// allows it. Routes are checked before the resource is loaded, so a rule that
+// needs resource attributes lets the request through to the handler, which
+// checks again once it has the resource.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    rules: Arc<Vec<Rule>>,
+}
+
+impl Policy {
+    pub fn load(path: Option<&str>) -> Result<Self, Error> {
+        let defaults: PolicyFile = serde_json::from_str(DEFAULT_POLICY)
+            .context("built in policy is invalid")
+            .map_err(Error::UnexpectedError)?;
+
+        let Some(path) = path else {
+            return Ok(Self { rules: Arc::new(defaults.rules) });
+        };
+
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read policy file {}", path))
+            .map_err(Error::UnexpectedError)?;
+        let file: PolicyFile = serde_json::from_str(&text)
+            .map_err(|e| Error::InvalidConfig(format!("policy file {}: {}", path, e)))?;
+        for rule in &file.rules {
+            if let Some(attr) = rule.when.as_ref().and_then(unknown_attr) {
+                return Err(Error::InvalidConfig(format!(
+                    "policy file {}: rule \"{}\" refers to {}, attributes start with principal, action or resource",
+                    path, rule.name, attr
+                )));
+            }
+        }
+
+        let mut rules = if file.include_defaults { defaults.rules } else { vec![] };
+        rules.extend(file.rules);
+        Ok(Self { rules: Arc::new(rules) })
+    }
+
+    // the built in rules followed by `rules`, for tests of code that takes a policy
+    #[cfg(test)]
+    pub(crate) fn with_rules(rules: Value) -> Self {
+        let mut policy = Self::load(None).unwrap();
+        let file: PolicyFile = serde_json::from_value(json!({ "rules": rules })).unwrap();
+        let mut all = policy.rules.to_vec();
+        all.extend(file.rules);
+        policy.rules = Arc::new(all);
+        policy
+    }
+
+    pub fn evaluate(&self, principal: &Principal, action: &str, resource: Option<&Value>) -> Decision {
+        let attrs = Attributes { principal: principal_attributes(principal), action, resource };
+        let rules: Vec<RuleTrace> = self
+            .rules
+            .iter()
+            .filter(|rule| rule.actions.iter().any(|pattern| grants(pattern, action)))
+            .map(|rule| RuleTrace {
+                rule: rule.name.clone(),
+                effect: rule.effect,
+                matched: rule.when.as_ref().map_or(Some(true), |cond| attrs.eval(cond, principal)),
+            })
+            .collect();
+
+        let find = |effect, matched| rules.iter().find(|t| t.effect == effect && t.matched == matched);
+        let (allowed, reason) = if let Some(t) = find(Effect::Deny, Some(true)) {
+            (false, format!("denied by \"{}\"", t.rule))
+        } else if let Some(t) = find(Effect::Allow, Some(true)) {
+            (true, format!("allowed by \"{}\"", t.rule))
+        } else if let Some(t) = find(Effect::Allow, None) {
+            (true, format!("allowed by \"{}\" pending the resource's attributes", t.rule))
+        } else {
+            (false, format!("no rule allows {}", action))
+        };
+
+        Decision { action: action.to_owned(), allowed, reason, rules }
+    }
+
+    // whether some rule on the action can only be decided from the resource's attributes
+    pub fn depends_on_resource(&self, principal: &Principal, action: &str) -> bool {
+        self.evaluate(principal, action, None).rules.iter().any(|t| t.matched.is_none())
+    }
+
+    pub fn authorize(&self, principal: &Principal, action: &str, resource: Option<&Value>) -> Result<(), Error> {
+        let decision = self.evaluate(principal, action, resource);
+        tracing::debug!(action, reason = %decision.reason, "policy decision");
+        if !decision.allowed {
+            tracing::warn!(action, subject = ?principal.subject, reason = %decision.reason, "request will be rejected");
+            return Err(Error::Unauthorized);
+        }
+        Ok(())
+    }
+}
+
+pub fn principal_attributes(principal: &Principal) -> Value {
+    let (kind, id) = match principal.subject {
+        Subject::User(id) => ("user", id),
+        Subject::ServiceAccount(id) => ("service_account", id),
+    };
+    json!({
+        "kind": kind,
+        "id": principal.user_id(),
+        "service_account_id": (kind == "service_account").then_some(id),
+        "permissions": principal.permissions,
+        "roles": principal.roles,
+        "impersonator_id": principal.impersonator,
+    })
+}
+
+fn unknown_attr(cond: &Condition) -> Option<&str> {
+    fn attr(op: &Operand) -> Option<&str> {
+        match op {
+            Operand::Attr { attr } => {
+                let root = attr.split('.').next().unwrap_or_default();
+                (!["principal", "action", "resource"].contains(&root)).then_some(attr.as_str())
+            }
+            Operand::Value(_) => None,
+        }
+    }
+    match cond {
+        Condition::All(conds) | Condition::Any(conds) => conds.iter().find_map(unknown_attr),
+        Condition::Not(cond) => unknown_attr(cond),
+        Condition::Has(_) => None,
+        Condition::Eq(a, b)
+        | Condition::Ne(a, b)
+        | Condition::Lt(a, b)
+        | Condition::Le(a, b)
+        | Condition::Gt(a, b)
+        | Condition::Ge(a, b)
+        | Condition::Contains(a, b) => attr(a).or_else(|| attr(b)),
+    }
+}
+
+struct Attributes<'a> {
+    principal: Value,
+    action: &'a str,
+    resource: Option<&'a Value>,
+}
+
+impl Attributes<'_> {
+    // None when the attribute belongs to a resource that was not supplied
+    fn resolve(&self, op: &Operand) -> Option<Value> {
+        let path = match op {
+            Operand::Attr { attr } => attr,
+            Operand::Value(value) => return Some(value.clone()),
+        };
+        let (root, rest) = path.split_once('.').unwrap_or((path, ""));
+        let base = match root {
+            "principal" => &self.principal,
+            "action" => return Some(Value::from(self.action)),
+            "resource" => self.resource?,
+            _ => return Some(Value::Null),
+        };
+        if rest.is_empty() {
+            return Some(base.clone());
+        }
+        let pointer = format!("/{}", rest.replace('.', "/"));
+        Some(base.pointer(&pointer).cloned().unwrap_or(Value::Null))
+    }
+
+    // three valued: Some(true), Some(false) or None for not known yet
+    fn eval(&self, cond: &Condition, principal: &Principal) -> Option<bool> {
+        let cmp = |a: &Operand, b: &Operand, f: fn(&Value, &Value) -> bool| {
+            Some(f(&self.resolve(a)?, &self.resolve(b)?))
+        };
+        match cond {
+            Condition::All(conds) => {
+                let mut ret = Some(true);
+                for cond in conds {
+                    match self.eval(cond, principal) {
+                        Some(false) => return Some(false),
+                        None => ret = None,
+                        Some(true) => {}
+                    }
+                }
+                ret
+            }
+            Condition::Any(conds) => {
+                let mut ret = Some(false);
+                for cond in conds {
+                    match self.eval(cond, principal) {
+                        Some(true) => return Some(true),
+                        None => ret = None,
+                        Some(false) => {}
+                    }
+                }
+                ret
+            }
+            Condition::Not(cond) => self.eval(cond, principal).map(|b| !b),
+            Condition::Has(code) => Some(principal.has(code)),
+            Condition::Eq(a, b) => cmp(a, b, equal),
+            Condition::Ne(a, b) => cmp(a, b, |a, b| !a.is_null() && !b.is_null() && !equal(a, b)),
+            Condition::Lt(a, b) => cmp(a, b, |a, b| order(a, b).is_some_and(|o| o.is_lt())),
+            Condition::Le(a, b) => cmp(a, b, |a, b| order(a, b).is_some_and(|o| o.is_le())),
+            Condition::Gt(a, b) => cmp(a, b, |a, b| order(a, b).is_some_and(|o| o.is_gt())),
+            Condition::Ge(a, b) => cmp(a, b, |a, b| order(a, b).is_some_and(|o| o.is_ge())),
+            Condition::Contains(a, b) => cmp(a, b, |a, b| match (a, b) {
+                (Value::Array(items), _) => items.iter().any(|item| equal(item, b)),
+                (Value::String(s), Value::String(sub)) => s.contains(sub.as_str()),
+                _ => false,
+            }),
+        }
+    }
+}
+
+// null never equals anything, so a missing owner is nobody's, not everybody's
+fn equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Null, _) | (_, Value::Null) => false,
+        (Value::Number(x), Value::Number(y)) => x.as_f64() == y.as_f64(),
+        _ => a == b,
+    }
+}
+
+fn order(a: &Value, b: &Value) -> Option<std::cmp::Ordering> {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64()?.partial_cmp(&y.as_f64()?),
+        (Value::String(x), Value::String(y)) => Some(x.cmp(y)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Policy;
+    use crate::session::{Principal, Subject};
+    use serde_json::json;
+
+    fn user(id: i64, perms: &[&str], roles: &[&str]) -> Principal {
+        Principal {
+            subject: Subject::User(id),
+            permissions: perms.iter().map(|p| p.to_string()).collect(),
+            roles: roles.iter().map(|r| r.to_string()).collect(),
+            token_hash: None,
+            impersonator: None,
+        }
+    }
+
+    #[test]
+    fn default_rules_follow_permission_codes() {
+        let policy = Policy::load(None).unwrap();
+        assert!(policy.evaluate(&user(1, &["movies:read"], &[]), "movies:read", None).allowed);
+        assert!(!policy.evaluate(&user(1, &["movies:read"], &[]), "movies:create", None).allowed);
+        assert!(policy.evaluate(&user(1, &["movies:write:any"], &[]), "movies:create", None).allowed);
+        assert!(!policy.evaluate(&user(1, &[], &[]), "users:admin", None).allowed);
+    }
+
+    #[test]
+    fn ownership_rule_waits_for_the_resource() {
+        let policy = Policy::load(None).unwrap();
+        let owner = user(7, &["movies:write:own"], &[]);
+
+        let decision = policy.evaluate(&owner, "movies:update", None);
+        assert!(decision.allowed);
+        assert!(decision.reason.contains("pending"));
+
+        assert!(policy.evaluate(&owner, "movies:update", Some(&json!({"created_by": 7}))).allowed);
+        assert!(!policy.evaluate(&owner, "movies:update", Some(&json!({"created_by": 8}))).allowed);
+        assert!(!policy.evaluate(&owner, "movies:delete", Some(&json!({"created_by": null}))).allowed);
+    }
+
+    #[test]
+    fn resource_dependence_follows_the_rules_on_the_action() {
+        let policy = Policy::with_rules(json!([{
+            "name": "nobody reads restored classics",
+            "effect": "deny",
+            "actions": ["movies:read"],
+            "when": {"contains": [{"attr": "resource.genres"}, "restored"]}
+        }]));
+        let reader = user(1, &["movies:read"], &[]);
+        assert!(!Policy::load(None).unwrap().depends_on_resource(&reader, "movies:read"));
+        assert!(policy.depends_on_resource(&reader, "movies:read"));
+        assert!(!policy.depends_on_resource(&reader, "movies:create"));
+    }
+
+    #[test]
+    fn attribute_rules_and_deny_overrides() {
+        let policy = Policy::with_rules(json!([
+            {
+                "name": "editors may update movies made before 1950",
+                "effect": "allow",
+                "actions": ["movies:update"],
+                "when": {"all": [
+                    {"contains": [{"attr": "principal.roles"}, "editor"]},
+                    {"lt": [{"attr": "resource.year"}, 1950]}
+                ]}
+            },
+            {
+                "name": "nobody deletes restored classics",
+                "effect": "deny",
+                "actions": ["movies:*"],
+                "when": {"all": [
+                    {"eq": [{"attr": "action"}, "movies:delete"]},
+                    {"contains": [{"attr": "resource.genres"}, "restored"]}
+                ]}
+            }
+        ]));
+
+        let editor = user(2, &[], &["editor"]);
+        assert!(policy.evaluate(&editor, "movies:update", Some(&json!({"year": 1942}))).allowed);
+        assert!(!policy.evaluate(&editor, "movies:update", Some(&json!({"year": 1999}))).allowed);
+
+        let admin = user(1, &["movies:write:any"], &[]);
+        let classic = json!({"year": 1927, "genres": ["restored"]});
+        let decision = policy.evaluate(&admin, "movies:delete", Some(&classic));
+        assert!(!decision.allowed);
+        assert!(decision.reason.contains("restored classics"));
+        assert!(policy.evaluate(&admin, "movies:update", Some(&classic)).allowed);
+    }
+}
@@ -0,0 +1,154 @@
+use anyhow::Context;
+use redis::Client;
+use warp::http::{header::HeaderName, HeaderMap, HeaderValue, Method};
+
+use crate::config::{RateLimitConfig, RateLimitKey};
+use crate::Error;
+
+pub const LIMIT_HEADER: &str = "x-ratelimit-limit";
+pub const REMAINING_HEADER: &str = "x-ratelimit-remaining";
+pub const RESET_HEADER: &str = "x-ratelimit-reset";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RouteGroup {
+    Auth,
+    Read,
+    Write,
+}
+
+impl RouteGroup {
+    pub fn of(method: &Method, path: &str) -> Self {
+        if path.starts_with("/v1/tokens") || (method == Method::POST && path == "/v1/users") {
+            RouteGroup::Auth
+        } else if method == Method::GET || method == Method::HEAD || method == Method::OPTIONS {
+            RouteGroup::Read
+        } else {
+            RouteGroup::Write
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            RouteGroup::Auth => "auth",
+            RouteGroup::Read => "read",
+            RouteGroup::Write => "write",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Quota {
+    pub limit: u64,
+    pub remaining: u64,
+    // seconds until the current window rolls over
+    pub reset: u64,
+    pub exceeded: bool,
+}
+
+impl Quota {
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        for (name, value) in [
+            (LIMIT_HEADER, self.limit),
+            (REMAINING_HEADER, self.remaining),
+            (RESET_HEADER, self.reset),
+        ] {
+            headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+        }
+    }
+}
+
+// A sliding window counter: the hits of the previous fixed window are weighted
+// by how much of it still overlaps the sliding window, which smooths out the
+// burst a plain fixed window allows at its boundary.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    redis: Client,
+    config: RateLimitConfig,
+}
+
+impl RateLimiter {
+    pub fn new(redis: Client, config: RateLimitConfig) -> Self {
+        Self { redis, config }
+    }
+
+    // auth endpoints are always keyed by ip, there is no user to key them by yet
+    pub fn keyed_by_user(&self, group: RouteGroup) -> bool {
+        group != RouteGroup::Auth && self.config.rate_limit_key == RateLimitKey::User
+    }
+
+    // returns None when the group is not limited
+    pub async fn hit(&self, group: RouteGroup, identity: &str) -> Result<Option<Quota>, Error> {
+        let limit = match group {
+            RouteGroup::Auth => self.config.rate_limit_auth,
+            RouteGroup::Read => self.config.rate_limit_read,
+            RouteGroup::Write => self.config.rate_limit_write,
+        } as u64;
+        if limit == 0 {
+            return Ok(None);
+        }
+
+        let window = self.config.rate_limit_window.as_secs().max(1);
+        let now = chrono::Utc::now().timestamp() as u64;
+        let (current, elapsed) = (now / window, now % window);
+        let key = |idx: u64| format!("ratelimit:{}:{}:{}", group.name(), identity, idx);
+
+        let mut conn = self
+            .redis
+            .get_async_connection()
+            .await
+            .context("failed to get redis conn in rate limiter")
+            .map_err(Error::UnexpectedError)?;
+
+        let (hits, previous): (u64, Option<u64>) = redis::pipe()
+            .atomic()
+            .cmd("INCR").arg(key(current))
+            .cmd("EXPIRE").arg(key(current)).arg(window * 2).ignore()
+            .cmd("GET").arg(key(current - 1))
+            .query_async(&mut conn)
+            .await
+            .context("failed to count request")
+            .map_err(Error::UnexpectedError)?;
+
+        Ok(Some(quota(limit, window, elapsed, previous.unwrap_or(0), hits)))
+    }
+}
+
+fn quota(limit: u64, window: u64, elapsed: u64, previous: u64, hits: u64) -> Quota {
+    let used = previous * (window - elapsed) / window + hits;
+    Quota {
+        limit,
+        remaining: limit.saturating_sub(used),
+        reset: window - elapsed,
+        exceeded: used > limit,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quota, RouteGroup};
+    use warp::http::Method;
+
+    #[test]
+    fn token_endpoints_and_signup_are_auth_group() {
+        assert_eq!(RouteGroup::of(&Method::POST, "/v1/tokens/authentication"), RouteGroup::Auth);
+        assert_eq!(RouteGroup::of(&Method::POST, "/v1/users"), RouteGroup::Auth);
+        assert_eq!(RouteGroup::of(&Method::GET, "/v1/movies"), RouteGroup::Read);
+        assert_eq!(RouteGroup::of(&Method::PATCH, "/v1/movies/1"), RouteGroup::Write);
+    }
+
+    #[test]
+    fn previous_window_is_weighted_by_overlap() {
+        // a quarter into the window, three quarters of the previous hits still count
+        let q = quota(100, 60, 15, 40, 10);
+        assert_eq!(q.remaining, 60);
+        assert_eq!(q.reset, 45);
+        assert!(!q.exceeded);
+    }
+
+    #[test]
+    fn exceeding_the_limit_leaves_nothing_remaining() {
+        let q = quota(10, 60, 59, 0, 11);
+        assert_eq!(q.remaining, 0);
+        assert!(q.exceeded);
+    }
+}
@@ -0,0 +1,133 @@
+use anyhow::Context;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::config::{AuthConfig, RegistrationPolicy};
+use crate::validator::Validator;
+use crate::Error;
+
+// Decides whether an email address may open a new account, for both password
+// signups and accounts created on a first oidc sign in. Invitations were vetted
+// by an admin, so only a closed registration turns them away.
+#[derive(Clone, Debug)]
+pub struct Registration {
+    policy: RegistrationPolicy,
+    allowed_domains: Arc<HashSet<String>>,
+    disposable_domains: Arc<HashSet<String>>,
+}
+
+impl Registration {
+    pub fn from_config(config: &AuthConfig) -> Result<Self, Error> {
+        let allowed_domains: HashSet<String> = config
+            .allowed_email_domains
+            .iter()
+            .map(|d| d.trim().to_lowercase())
+            .filter(|d| !d.is_empty())
+            .collect();
+        if config.registration_policy == RegistrationPolicy::DomainAllowlist && allowed_domains.is_empty() {
+            return Err(Error::InvalidConfig(
+                "domain_allowlist registration requires at least one allowed email domain".to_owned(),
+            ));
+        }
+
+        let disposable_domains = match config.disposable_domains_file.as_deref() {
+            Some(path) => std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read disposable domains file {}", path))
+                .map_err(Error::UnexpectedError)
+                .map(|data| parse_domains(&data))?,
+            None => HashSet::new(),
+        };
+
+        Ok(Self {
+            policy: config.registration_policy,
+            allowed_domains: Arc::new(allowed_domains),
+            disposable_domains: Arc::new(disposable_domains),
+        })
+    }
+
+    pub fn check(&self, email: &str, invited: bool) -> Result<(), Error> {
+        let mut v = Validator::new();
+        let domain = email.rsplit('@').next().unwrap_or_default().to_lowercase();
+
+        match self.policy {
+            RegistrationPolicy::Closed => v.add_err("registration", "is closed, new accounts are not being accepted"),
+            RegistrationPolicy::InviteOnly if !invited => {
+                v.add_err("invitation", "must be provided, registration is by invitation only")
+            }
+            RegistrationPolicy::DomainAllowlist if !invited && !self.allowed_domains.contains(&domain) => {
+                v.add_err("email", "must belong to an allowed email domain")
+            }
+            _ => {}
+        }
+        if !invited && self.is_disposable(&domain) {
+            v.add_err("email", "must not belong to a disposable email provider");
+        }
+
+        if !v.valid() {
+            return Err(Error::Validation(v.get_err()));
+        }
+        Ok(())
+    }
+
+    // providers hand out subdomains freely, so every parent domain is checked too
+    fn is_disposable(&self, domain: &str) -> bool {
+        let mut rest = domain;
+        loop {
+            if self.disposable_domains.contains(rest) {
+                return true;
+            }
+            match rest.split_once('.') {
+                Some((_, parent)) if parent.contains('.') => rest = parent,
+                _ => return false,
+            }
+        }
+    }
+}
+
+// one domain per line, blank lines and `#` comments are skipped
+fn parse_domains(data: &str) -> HashSet<String> {
+    data.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_domains, Registration};
+    use crate::config::RegistrationPolicy;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    fn registration(policy: RegistrationPolicy) -> Registration {
+        Registration {
+            policy,
+            allowed_domains: Arc::new(HashSet::from(["example.com".to_owned()])),
+            disposable_domains: Arc::new(parse_domains("# throwaways\nMailinator.com\n\ntempmail.io  # and more\n")),
+        }
+    }
+
+    #[test]
+    fn allowlist_only_admits_listed_domains() {
+        let r = registration(RegistrationPolicy::DomainAllowlist);
+        assert!(r.check("bob@Example.com", false).is_ok());
+        assert!(r.check("bob@sub.example.com", false).is_err());
+        assert!(r.check("bob@other.org", true).is_ok());
+    }
+
+    #[test]
+    fn disposable_domains_and_their_subdomains_are_rejected() {
+        let r = registration(RegistrationPolicy::Open);
+        assert!(r.check("bob@mailinator.com", false).is_err());
+        assert!(r.check("bob@x.tempmail.io", false).is_err());
+        assert!(r.check("bob@tempmail.io.example.com", false).is_ok());
+    }
+
+    #[test]
+    fn invitations_are_required_or_refused_by_policy() {
+        assert!(registration(RegistrationPolicy::InviteOnly).check("bob@other.org", false).is_err());
+        assert!(registration(RegistrationPolicy::InviteOnly).check("bob@other.org", true).is_ok());
+        assert!(registration(RegistrationPolicy::Closed).check("bob@example.com", true).is_err());
+    }
+}
@@ -1,28 +1,72 @@
 use redis::Client;
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use tracing::instrument;
-use warp::{http::Method, Filter, Reply};
+use warp::{http::Method, path::FullPath, Filter, Reply};
 
+use crate::config::{AuthConfig, RateLimitConfig};
 use crate::errors::{return_error, Error};
+use crate::api_key::ApiKey;
+use crate::auth_cache::AuthCache;
+use crate::breach_list::BreachList;
+use crate::handlers::admin;
+use crate::handlers::api_key;
+use crate::handlers::authz;
 use crate::handlers::movie;
+use crate::handlers::oidc;
+use crate::handlers::session;
 use crate::handlers::token;
+use crate::handlers::totp;
 use crate::handlers::user;
+use crate::jwt::JwtCodec;
+use crate::oidc::Oidc;
+use crate::policy::Policy;
+use crate::rate_limit::{Quota, RateLimiter, RouteGroup};
+use crate::registration::Registration;
+use crate::session::{ClientInfo, Principal, Subject};
 use crate::store::Store;
-use crate::token::{Token, SCOPE_AUTHENTICATION};
+use crate::token::Token;
 use crate::validator::Validator;
 
-fn with_perm(
-    perm_code: &'static str,
+fn with_action(
+    action: &'static str,
 ) -> impl Filter<Extract = (&'static str,), Error = std::convert::Infallible> + Copy {
-    warp::any().map(move || perm_code)
+    warp::any().map(move || action)
+}
+
+fn with_client() -> impl Filter<Extract = (ClientInfo,), Error = warp::Rejection> + Clone {
+    warp::addr::remote()
+        .and(warp::header::optional::<String>("User-Agent"))
+        .map(|addr: Option<SocketAddr>, user_agent: Option<String>| ClientInfo {
+            ip: addr.map(|a| a.ip().to_string()),
+            user_agent,
+        })
+}
+
+// like `warp::body::json` but an empty body deserializes to the defaults
+fn optional_json<T>() -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone
+where
+    T: serde::de::DeserializeOwned + Default + Send,
+{
+    warp::body::bytes().and_then(|body: warp::hyper::body::Bytes| async move {
+        if body.is_empty() {
+            return Ok(T::default());
+        }
+        serde_json::from_slice(&body).map_err(|_| {
+            let mut v = Validator::new();
+            v.add_err("body", "must be a valid JSON object");
+            warp::reject::custom(Error::Validation(v.get_err()))
+        })
+    })
 }
 
 #[instrument]
-async fn require_permission(
-    perm_code: &'static str,
+async fn authenticate(
     tok_str: Option<String>,
+    client: ClientInfo,
     store: Store,
-) -> Result<(), warp::Rejection> {
+    jwt: Option<JwtCodec>,
+) -> Result<Principal, warp::Rejection> {
     let Some(tok_str) = tok_str  else {
         return Err(Error::AuthenticationRequired.into());
     };
@@ -32,57 +76,256 @@ async fn require_permission(
         return Err(Error::InvalidAuthenticationToken.into());
     }
 
+    // opaque tokens stay valid in jwt mode so that switching modes doesn't log everyone out
+    if let Some(jwt) = jwt.filter(|_| vs[1].contains('.')) {
+        let claims = jwt.verify(vs[1])?;
+        if !claims.act {
+            return Err(Error::InactiveAccount.into());
+        }
+        let user_id = claims.sub.parse().map_err(|_| Error::InvalidAuthenticationToken)?;
+
+        return Ok(Principal {
+            subject: Subject::User(user_id),
+            permissions: claims.perms,
+            roles: claims.roles,
+            token_hash: None,
+            impersonator: None,
+        });
+    }
+
     let mut v = Validator::new();
     Token::validate(&mut v, vs[1]);
     if !v.valid() {
         return Err(Error::InvalidAuthenticationToken.into());
     }
 
-    let user = store
-        .get_user_by_token(SCOPE_AUTHENTICATION, vs[1])
+    let token_hash = Token::gen_hash(vs[1]);
+    let auth = match store.auth_cache.get(&token_hash).await {
+        Some(auth) => auth,
+        None => {
+            // taken before the lookup so an invalidation racing it keeps the result out of the cache
+            let epoch = store.auth_cache.epoch().await;
+            // the token is only touched on a miss, so last use is tracked to within one cache ttl
+            let auth = store
+                .authenticate_token(&token_hash, &client)
+                .await
+                .map_err(|e| match e {
+                    Error::RecordNotFound => Error::InvalidAuthenticationToken,
+                    _ => e,
+                })?;
+
+            if !auth.activated {
+                return Err(Error::InactiveAccount.into());
+            }
+
+            store.auth_cache.put(&token_hash, auth.clone(), epoch).await;
+            auth
+        }
+    };
+
+    // the audit trail for impersonation, the request span carries method and path
+    if let Some(impersonator_id) = auth.impersonator_id {
+        tracing::info!(user_id = auth.user_id, impersonator_id, "request made while impersonating");
+    }
+
+    Ok(Principal {
+        subject: Subject::User(auth.user_id),
+        permissions: auth.permissions,
+        roles: auth.roles,
+        token_hash: Some(token_hash),
+        impersonator: auth.impersonator_id,
+    })
+}
+
+// credentials belong to the real user, an admin acting as them may look but not touch
+async fn forbid_impersonation(principal: Principal) -> Result<Principal, warp::Rejection> {
+    if let Some(impersonator_id) = principal.impersonator {
+        tracing::warn!(user_id = ?principal.user_id(), impersonator_id, "credential change refused while impersonating");
+        return Err(Error::Impersonating.into());
+    }
+    Ok(principal)
+}
+
+#[instrument(skip(key))]
+async fn authenticate_api_key(key: &str, store: &Store) -> Result<Principal, Error> {
+    let mut v = Validator::new();
+    ApiKey::validate(&mut v, key);
+    if !v.valid() {
+        return Err(Error::InvalidAuthenticationToken);
+    }
+
+    let key = store
+        .use_api_key(&Token::gen_hash(key))
         .await
         .map_err(|e| match e {
             Error::RecordNotFound => Error::InvalidAuthenticationToken,
             _ => e,
         })?;
 
-    if !user.activated {
-        return Err(Error::InactiveAccount.into());
-    }
+    Ok(Principal {
+        subject: Subject::ServiceAccount(key.service_account_id),
+        permissions: key.permissions,
+        roles: vec![],
+        token_hash: None,
+        impersonator: None,
+    })
+}
 
-    let perms = store.permissions_by_user(user.id).await?;
-    tracing::debug!(request_perm= ?perm_code, have_perms= ?perms, "before search perm list");
+#[instrument(skip(api_key))]
+async fn identify(
+    api_key: Option<String>,
+    tok_str: Option<String>,
+    client: ClientInfo,
+    store: Store,
+    jwt: Option<JwtCodec>,
+) -> Result<Principal, warp::Rejection> {
+    let api_key = api_key.or_else(|| {
+        tok_str
+            .as_deref()
+            .and_then(|t| t.strip_prefix("ApiKey "))
+            .map(str::to_owned)
+    });
 
-    if !perms.iter().any(|e| e == perm_code) {
-        tracing::warn!("request will be rejected");
-        return Err(Error::Unauthorized.into());
-    }
+    let principal = match api_key {
+        Some(key) => authenticate_api_key(&key, &store).await?,
+        None => authenticate(tok_str, client, store, jwt).await?,
+    };
 
+    Ok(principal)
+}
+
+// routes are checked without the resource, handlers that load one check again
+#[instrument(skip(principal, policy))]
+async fn require_permission(
+    action: &'static str,
+    principal: Principal,
+    policy: Policy,
+) -> Result<Principal, warp::Rejection> {
+    tracing::debug!(action, have_perms = ?principal.permissions, "before policy check");
+    policy.authorize(&principal, action, None)?;
     tracing::debug!("permission check pass");
 
-    Ok(())
+    Ok(principal)
+}
+
+// only resolves the caller for keying the rate limit, the route still authenticates.
+// This never touches the database: opaque tokens the auth cache doesn't know yet,
+// junk ones included, are counted against the ip until authenticate caches them
+async fn resolve_user_id(tok_str: &str, auth_cache: &AuthCache, jwt: Option<&JwtCodec>) -> Option<i64> {
+    if let Some(jwt) = jwt.filter(|_| tok_str.contains('.')) {
+        return jwt.verify(tok_str).ok().and_then(|claims| claims.sub.parse().ok());
+    }
+
+    let mut v = Validator::new();
+    Token::validate(&mut v, tok_str);
+    if !v.valid() {
+        return None;
+    }
+    auth_cache.get(&Token::gen_hash(tok_str)).await.map(|auth| auth.user_id)
+}
+
+#[instrument(skip_all, fields(path = %path.as_str()))]
+async fn check_rate_limit(
+    method: Method,
+    path: FullPath,
+    client: ClientInfo,
+    tok_str: Option<String>,
+    limiter: RateLimiter,
+    store: Store,
+    jwt: Option<JwtCodec>,
+) -> Result<Option<Quota>, warp::Rejection> {
+    let group = RouteGroup::of(&method, path.as_str());
+
+    let mut identity = None;
+    if limiter.keyed_by_user(group) {
+        if let Some(tok) = tok_str.as_deref().and_then(|t| t.strip_prefix("Bearer ")) {
+            identity = resolve_user_id(tok, &store.auth_cache, jwt.as_ref())
+                .await
+                .map(|id| format!("user:{}", id));
+        }
+    }
+    // unauthenticated callers, and callers presenting junk tokens, share their ip's quota
+    let identity = identity.unwrap_or_else(|| format!("ip:{}", client.ip.as_deref().unwrap_or("unknown")));
+
+    match limiter.hit(group, &identity).await {
+        Ok(Some(quota)) if quota.exceeded => {
+            tracing::warn!(identity, ?group, "rate limit exceeded");
+            Err(Error::RateLimited { limit: quota.limit, reset: quota.reset }.into())
+        }
+        Ok(quota) => Ok(quota),
+        Err(e) => {
+            tracing::error!(err = %e, "rate limiter unavailable, allowing request");
+            Ok(None)
+        }
+    }
 }
 
-pub fn build_routes(store: Store, redis: Client) -> impl Filter<Extract = impl Reply> + Clone {
+// every shared handle the handlers take is wired through here
+#[allow(clippy::too_many_arguments)]
+pub fn build_routes(
+    store: Store,
+    redis: Client,
+    auth_config: AuthConfig,
+    rate_limit_config: RateLimitConfig,
+    jwt: Option<JwtCodec>,
+    breach_list: Option<BreachList>,
+    registration: Registration,
+    oidc: Oidc,
+    policy: Policy,
+) -> impl Filter<Extract = impl Reply> + Clone {
+    let limiter = RateLimiter::new(redis.clone(), rate_limit_config);
     let store_filter = warp::any().map(move || store.clone());
     let redis_filter = warp::any().map(move || redis.clone());
+    let auth_config_filter = warp::any().map(move || auth_config.clone());
+    let jwt_filter = warp::any().map(move || jwt.clone());
+    let breach_list_filter = warp::any().map(move || breach_list.clone());
+    let registration_filter = warp::any().map(move || registration.clone());
+    let oidc_filter = warp::any().map(move || oidc.clone());
+    let policy_filter = warp::any().map(move || policy.clone());
+
+    let rate_limit = warp::method()
+        .and(warp::path::full())
+        .and(with_client())
+        .and(warp::header::optional::<String>("Authorization"))
+        .and(warp::any().map(move || limiter.clone()))
+        .and(store_filter.clone())
+        .and(jwt_filter.clone())
+        .and_then(check_rate_limit);
 
     let cors = warp::cors()
         .allow_any_origin()
         .allow_header("content-type")
-        .allow_methods(&[Method::PATCH, Method::DELETE, Method::GET, Method::POST]);
+        .allow_methods(&[Method::PATCH, Method::DELETE, Method::GET, Method::POST, Method::PUT]);
 
-    let write_perm = with_perm("movies:write")
-        .and(warp::header::optional::<String>("Authorization"))
+    let credentials = warp::header::optional::<String>("Authorization")
+        .and(with_client())
         .and(store_filter.clone())
-        .and_then(require_permission)
-        .untuple_one();
+        .and(jwt_filter.clone());
 
-    let read_perm = with_perm("movies:read")
-        .and(warp::header::optional::<String>("Authorization"))
-        .and(store_filter.clone())
-        .and_then(require_permission)
-        .untuple_one();
+    let authenticated = credentials.clone().and_then(authenticate);
+
+    let principal = warp::header::optional::<String>("X-Api-Key")
+        .and(credentials.clone())
+        .and_then(identify);
+
+    let permit = |action: &'static str| {
+        with_action(action)
+            .and(principal.clone())
+            .and(policy_filter.clone())
+            .and_then(require_permission)
+    };
+
+    let movie_read = permit("movies:read");
+    let movie_create = permit("movies:create");
+    let movie_update = permit("movies:update");
+    let movie_delete = permit("movies:delete");
+    let service_admin = permit("service-accounts:admin");
+    let users_admin = permit("users:admin");
+    let users_impersonate = permit("users:impersonate").and_then(forbid_impersonation);
+
+    // tokens impersonating a user may look at credentials but never change them
+    let credential_owner = authenticated.clone().and_then(forbid_impersonation);
+    let credential_admin = service_admin.clone().and_then(forbid_impersonation);
 
     let prefix = warp::path!("v1" / ..);
 
@@ -91,7 +334,8 @@ pub fn build_routes(store: Store, redis: Client) -> impl Filter<Extract = impl R
         .and(warp::path::param::<i64>())
         .and(warp::path::end())
         .and(store_filter.clone())
-        .and(read_perm.clone())
+        .and(movie_read.clone())
+        .and(policy_filter.clone())
         .and_then(movie::get_movie);
 
     let add_movie = warp::post()
@@ -99,7 +343,8 @@ pub fn build_routes(store: Store, redis: Client) -> impl Filter<Extract = impl R
         .and(warp::path::end())
         .and(store_filter.clone())
         .and(warp::body::json())
-        .and(write_perm.clone())
+        .and(movie_create)
+        .and(policy_filter.clone())
         .and_then(movie::add_movie);
 
     let update_movie = warp::patch()
@@ -108,7 +353,8 @@ pub fn build_routes(store: Store, redis: Client) -> impl Filter<Extract = impl R
         .and(warp::path::end())
         .and(warp::body::json())
         .and(store_filter.clone())
-        .and(write_perm.clone())
+        .and(movie_update)
+        .and(policy_filter.clone())
         .and_then(movie::update_movie);
 
     let remove_movie = warp::delete()
@@ -116,7 +362,8 @@ pub fn build_routes(store: Store, redis: Client) -> impl Filter<Extract = impl R
         .and(warp::path::param::<i64>())
         .and(warp::path::end())
         .and(store_filter.clone())
-        .and(write_perm)
+        .and(movie_delete)
+        .and(policy_filter.clone())
         .and_then(movie::remove_movie);
 
     let search_movie = warp::get()
@@ -124,15 +371,29 @@ pub fn build_routes(store: Store, redis: Client) -> impl Filter<Extract = impl R
         .and(warp::path::end())
         .and(warp::query::<HashMap<String, String>>())
         .and(store_filter.clone())
-        .and(read_perm)
+        .and(movie_read)
+        .and(policy_filter.clone())
         .and_then(movie::search_movie);
 
+    let explain = warp::get()
+        .and(warp::path!("authz" / "explain"))
+        .and(warp::path::end())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(principal)
+        .and(store_filter.clone())
+        .and(policy_filter)
+        .and_then(authz::explain);
+
     let reg_user = warp::post()
         .and(warp::path("users"))
         .and(warp::path::end())
         .and(warp::body::json())
+        .and(warp::header::optional::<String>("Accept-Language"))
         .and(store_filter.clone())
         .and(redis_filter.clone())
+        .and(auth_config_filter.clone())
+        .and(breach_list_filter.clone())
+        .and(registration_filter.clone())
         .and_then(user::register);
 
     let activate = warp::put()
@@ -147,45 +408,357 @@ pub fn build_routes(store: Store, redis: Client) -> impl Filter<Extract = impl R
         .and(warp::path::end())
         .and(warp::body::json())
         .and(store_filter.clone())
+        .and(auth_config_filter.clone())
+        .and(breach_list_filter)
         .and_then(user::password_update);
 
+    let list_sessions = warp::get()
+        .and(warp::path!("users" / "me" / "sessions"))
+        .and(warp::path::end())
+        .and(authenticated)
+        .and(store_filter.clone())
+        .and(auth_config_filter.clone())
+        .and_then(session::list_sessions);
+
+    let revoke_session = warp::delete()
+        .and(warp::path!("users" / "me" / "sessions" / String))
+        .and(warp::path::end())
+        .and(credential_owner.clone())
+        .and(store_filter.clone())
+        .and(auth_config_filter.clone())
+        .and_then(session::revoke_session);
+
+    let totp_enrol = warp::post()
+        .and(warp::path!("users" / "me" / "totp"))
+        .and(warp::path::end())
+        .and(credential_owner.clone())
+        .and(store_filter.clone())
+        .and(auth_config_filter.clone())
+        .and_then(totp::enrol);
+
+    let totp_verify = warp::post()
+        .and(warp::path!("users" / "me" / "totp" / "verify"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(credential_owner)
+        .and(store_filter.clone())
+        .and_then(totp::verify);
+
+    let add_service_account = warp::post()
+        .and(warp::path("service-accounts"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(store_filter.clone())
+        .and(credential_admin.clone())
+        .and_then(api_key::add_service_account);
+
+    let list_service_accounts = warp::get()
+        .and(warp::path("service-accounts"))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and(service_admin.clone())
+        .and_then(api_key::list_service_accounts);
+
+    let add_api_key = warp::post()
+        .and(warp::path!("service-accounts" / i64 / "keys"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(store_filter.clone())
+        .and(credential_admin.clone())
+        .and_then(api_key::add_api_key);
+
+    let list_api_keys = warp::get()
+        .and(warp::path!("service-accounts" / i64 / "keys"))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and(service_admin)
+        .and_then(api_key::list_api_keys);
+
+    let revoke_api_key = warp::delete()
+        .and(warp::path!("service-accounts" / i64 / "keys" / i64))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and(credential_admin)
+        .and_then(api_key::revoke_api_key);
+
+    let search_users = warp::get()
+        .and(warp::path!("admin" / "users"))
+        .and(warp::path::end())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(store_filter.clone())
+        .and(users_admin.clone())
+        .and_then(admin::search_users);
+
+    let get_user = warp::get()
+        .and(warp::path!("admin" / "users" / i64))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and(auth_config_filter.clone())
+        .and(users_admin.clone())
+        .and_then(admin::get_user);
+
+    let set_user_status = warp::patch()
+        .and(warp::path!("admin" / "users" / i64))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(store_filter.clone())
+        .and(users_admin.clone())
+        .and_then(admin::set_user_status);
+
+    let force_password_reset = warp::post()
+        .and(warp::path!("admin" / "users" / i64 / "password-reset"))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and(redis_filter.clone())
+        .and(users_admin.clone().and_then(forbid_impersonation))
+        .and_then(admin::force_password_reset);
+
+    let impersonate = warp::post()
+        .and(warp::path!("admin" / "users" / i64 / "impersonate"))
+        .and(warp::path::end())
+        .and(with_client())
+        .and(store_filter.clone())
+        .and(redis_filter.clone())
+        .and(auth_config_filter.clone())
+        .and(users_impersonate)
+        .and_then(admin::impersonate);
+
+    let remove_user = warp::delete()
+        .and(warp::path!("admin" / "users" / i64))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and(users_admin.clone())
+        .and_then(admin::remove_user);
+
+    let list_roles = warp::get()
+        .and(warp::path!("admin" / "roles"))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and(users_admin.clone())
+        .and_then(admin::list_roles);
+
+    let add_role = warp::post()
+        .and(warp::path!("admin" / "roles"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(store_filter.clone())
+        .and(users_admin.clone())
+        .and_then(admin::add_role);
+
+    let assign_role = warp::put()
+        .and(warp::path!("admin" / "users" / i64 / "roles" / String))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and(users_admin.clone())
+        .and_then(admin::assign_role);
+
+    let unassign_role = warp::delete()
+        .and(warp::path!("admin" / "users" / i64 / "roles" / String))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and(users_admin.clone())
+        .and_then(admin::unassign_role);
+
+    let grant_permission = warp::put()
+        .and(warp::path!("admin" / "users" / i64 / "permissions" / String))
+        .and(warp::path::end())
+        .and(optional_json())
+        .and(store_filter.clone())
+        .and(users_admin.clone())
+        .and_then(admin::grant_permission);
+
+    let revoke_permission = warp::delete()
+        .and(warp::path!("admin" / "users" / i64 / "permissions" / String))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and(users_admin.clone())
+        .and_then(admin::revoke_permission);
+
+    let list_invitations = warp::get()
+        .and(warp::path!("admin" / "invitations"))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and(users_admin.clone())
+        .and_then(admin::list_invitations);
+
+    let invite_user = warp::post()
+        .and(warp::path!("admin" / "invitations"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(store_filter.clone())
+        .and(redis_filter.clone())
+        .and(users_admin.clone())
+        .and_then(admin::invite_user);
+
+    let revoke_invitation = warp::delete()
+        .and(warp::path!("admin" / "invitations" / i64))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and(users_admin)
+        .and_then(admin::revoke_invitation);
+
     let auth_token = warp::post()
         .and(warp::path!("tokens" / "authentication"))
         .and(warp::path::end())
         .and(warp::body::json())
+        .and(with_client())
         .and(store_filter.clone())
+        .and(redis_filter.clone())
+        .and(auth_config_filter.clone())
+        .and(jwt_filter.clone())
         .and_then(token::gen_auth_token);
 
+    let refresh_token = warp::post()
+        .and(warp::path!("tokens" / "refresh"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(with_client())
+        .and(store_filter.clone())
+        .and(auth_config_filter.clone())
+        .and(jwt_filter.clone())
+        .and_then(token::refresh_auth_token);
+
+    let mfa_token = warp::post()
+        .and(warp::path!("tokens" / "mfa"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(with_client())
+        .and(store_filter.clone())
+        .and(redis_filter.clone())
+        .and(auth_config_filter.clone())
+        .and(jwt_filter.clone())
+        .and_then(token::complete_mfa);
+
     let activate_token = warp::post()
         .and(warp::path!("tokens" / "activation"))
         .and(warp::path::end())
         .and(warp::body::json())
         .and(store_filter.clone())
         .and(redis_filter.clone())
+        .and(auth_config_filter.clone())
         .and_then(token::gen_activation_token);
 
     let reset_token = warp::post()
         .and(warp::path!("tokens" / "password-reset"))
         .and(warp::path::end())
         .and(warp::body::json())
+        .and(store_filter.clone())
+        .and(redis_filter.clone())
+        .and(auth_config_filter.clone())
+        .and_then(token::gen_reset_token);
+
+    let magic_link = warp::post()
+        .and(warp::path!("tokens" / "magic-link"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(store_filter.clone())
+        .and(redis_filter.clone())
+        .and(auth_config_filter.clone())
+        .and_then(token::gen_magic_link);
+
+    let magic_link_token = warp::post()
+        .and(warp::path!("tokens" / "magic-link" / "authentication"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(with_client())
+        .and(store_filter.clone())
+        .and(auth_config_filter.clone())
+        .and(jwt_filter.clone())
+        .and_then(token::exchange_magic_link);
+
+    let oidc_authorize = warp::get()
+        .and(warp::path!("oidc" / String / "authorize"))
+        .and(warp::path::end())
+        .and(oidc_filter.clone())
+        .and(redis_filter.clone())
+        .and_then(oidc::authorize);
+
+    let oidc_token = warp::post()
+        .and(warp::path!("tokens" / "oidc"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(with_client())
         .and(store_filter)
         .and(redis_filter)
-        .and_then(token::gen_reset_token);
+        .and(auth_config_filter)
+        .and(jwt_filter)
+        .and(registration_filter)
+        .and(oidc_filter)
+        .and_then(oidc::gen_oidc_token);
+
+    let movie_routes = get_movie
+        .or(add_movie)
+        .or(update_movie)
+        .or(remove_movie)
+        .or(search_movie)
+        .boxed();
+
+    let user_routes = reg_user
+        .or(activate)
+        .or(password_update)
+        .or(list_sessions)
+        .or(revoke_session)
+        .or(totp_enrol)
+        .or(totp_verify)
+        .boxed();
+
+    let authz_routes = explain.boxed();
+
+    let service_account_routes = add_service_account
+        .or(list_service_accounts)
+        .or(add_api_key)
+        .or(list_api_keys)
+        .or(revoke_api_key)
+        .boxed();
+
+    let admin_routes = search_users
+        .or(get_user)
+        .or(set_user_status)
+        .or(force_password_reset)
+        .or(impersonate)
+        .or(remove_user)
+        .or(list_roles)
+        .or(add_role)
+        .or(assign_role)
+        .or(unassign_role)
+        .or(grant_permission)
+        .or(revoke_permission)
+        .or(list_invitations)
+        .or(invite_user)
+        .or(revoke_invitation)
+        .boxed();
+
+    let token_routes = auth_token
+        .or(refresh_token)
+        .or(mfa_token)
+        .or(activate_token)
+        .or(reset_token)
+        .or(magic_link)
+        .or(magic_link_token)
+        .or(oidc_authorize)
+        .or(oidc_token)
+        .boxed();
 
+    // only /v1 requests count, a 404 for anything else doesn't use up the client's quota
     prefix
+        .and(rate_limit)
         .and(
-            get_movie
-            .or(add_movie)
-            .or(update_movie)
-            .or(remove_movie)
-            .or(search_movie)
-            .or(reg_user)
-            .or(activate)
-            .or(password_update)
-            .or(auth_token)
-            .or(activate_token)
-            .or(reset_token),
+            movie_routes
+            .or(user_routes)
+            .or(service_account_routes)
+            .or(admin_routes)
+            .or(authz_routes)
+            .or(token_routes)
+            // rejections are turned into replies here so that they get the quota headers too
+            .recover(return_error),
     )
+    .map(|quota: Option<Quota>, reply| {
+        let mut res = Reply::into_response(reply);
+        if let Some(quota) = quota {
+            quota.apply(res.headers_mut());
+        }
+        res
+    })
     .with(cors)
     .with(warp::trace::request())
     .recover(return_error)
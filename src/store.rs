@@ -2,19 +2,29 @@ mod movie;
 mod user;
 mod token;
 mod permission;
+mod api_key;
+mod totp;
+mod identity;
+mod role;
+mod invitation;
+mod password_history;
 
 use sqlx::postgres::{PgPool, PgPoolOptions};
 
+use crate::auth_cache::AuthCache;
 use crate::config;
 use crate::Error;
 
+// writes that change what a token authorizes invalidate auth_cache themselves,
+// so callers never have to remember to
 #[derive(Debug, Clone)]
 pub struct Store {
     pub db: PgPool,
+    pub auth_cache: AuthCache,
 }
 
 impl Store {
-    pub fn new(dbc: &config::DbConfig) -> Result<Self, Error> {
+    pub fn new(dbc: &config::DbConfig, auth_cache: AuthCache) -> Result<Self, Error> {
         tracing::warn!("{}", dbc.db_dsn);
         let db_pool = PgPoolOptions::new()
             .max_connections(dbc.db_max_conn)
@@ -22,6 +32,6 @@ impl Store {
             .connect_lazy(&dbc.db_dsn)
             .map_err(Error::InitDatabase)?;
 
-        Ok(Store { db: db_pool })
+        Ok(Store { db: db_pool, auth_cache })
     }
 }
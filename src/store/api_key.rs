@@ -0,0 +1,166 @@
+use super::Store;
+
+use chrono::Utc;
+
+use crate::api_key::{ApiKey, ServiceAccount};
+use crate::Error;
+
+impl Store {
+    pub async fn add_service_account(&self, name: &str, created_by: Option<i64>) -> Result<ServiceAccount, Error> {
+        match sqlx::query_as!(
+            ServiceAccount,
+            r#"
+               insert into service_accounts (name, created_by)
+               values ($1, $2)
+               returning id, created_at, name, created_by
+            "#,
+            name,
+            created_by,
+        )
+        .fetch_one(&self.db)
+        .await
+        {
+            Ok(account) => Ok(account),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                match e {
+                    sqlx::Error::Database(ref de) if de.is_unique_violation() => Err(Error::DuplicateName),
+                    _ => Err(Error::DatabaseQuery(e)),
+                }
+            }
+        }
+    }
+
+    pub async fn list_service_accounts(&self) -> Result<Vec<ServiceAccount>, Error> {
+        let accounts = sqlx::query_as!(
+            ServiceAccount,
+            r#"
+               select id, created_at, name, created_by
+               from service_accounts
+               order by id
+            "#,
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?;
+
+        Ok(accounts)
+    }
+
+    pub async fn add_api_key(&self, key: &mut ApiKey) -> Result<(), Error> {
+        match sqlx::query!(
+            r#"
+               insert into api_keys (service_account_id, hash, name, permissions, expiry, created_at)
+               values ($1, $2, $3, $4, $5, $6)
+               returning id
+            "#,
+            key.service_account_id,
+            key.hash,
+            key.name,
+            &key.permissions,
+            key.expiry,
+            key.created_at,
+        )
+        .map(|ret| {
+            key.id = ret.id;
+        })
+        .fetch_one(&self.db)
+        .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                match e {
+                    sqlx::Error::Database(ref de) if de.is_foreign_key_violation() => Err(Error::RecordNotFound),
+                    _ => Err(Error::DatabaseQuery(e)),
+                }
+            }
+        }
+    }
+
+    pub async fn api_keys_by_account(&self, service_account_id: i64) -> Result<Vec<ApiKey>, Error> {
+        let keys = sqlx::query!(
+            r#"
+               select id, service_account_id, name, permissions, expiry, created_at, last_used_at
+               from api_keys
+               where service_account_id = $1
+               order by id
+            "#,
+            service_account_id,
+        )
+        .map(|row| ApiKey {
+            id: row.id,
+            service_account_id: row.service_account_id,
+            name: row.name,
+            permissions: row.permissions,
+            expiry: row.expiry,
+            created_at: row.created_at,
+            last_used_at: row.last_used_at,
+            ..ApiKey::default()
+        })
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?;
+
+        Ok(keys)
+    }
+
+    pub async fn delete_api_key(&self, service_account_id: i64, id: i64) -> Result<u64, Error> {
+        let remove_count = sqlx::query!(
+            r#"
+               delete from api_keys where service_account_id = $1 and id = $2
+            "#,
+            service_account_id,
+            id,
+        )
+        .execute(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?
+        .rows_affected();
+
+        Ok(remove_count)
+    }
+
+    // looks up an unexpired key and records its use in the same round trip
+    pub async fn use_api_key(&self, hash: &[u8]) -> Result<ApiKey, Error> {
+        let key = sqlx::query!(
+            r#"
+               update api_keys set last_used_at = $2
+               where hash = $1 and (expiry is null or expiry > $2)
+               returning id, service_account_id, name, permissions, expiry, created_at, last_used_at
+            "#,
+            hash,
+            Utc::now(),
+        )
+        .map(|row| ApiKey {
+            id: row.id,
+            service_account_id: row.service_account_id,
+            name: row.name,
+            permissions: row.permissions,
+            expiry: row.expiry,
+            created_at: row.created_at,
+            last_used_at: row.last_used_at,
+            ..ApiKey::default()
+        })
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            match e {
+                sqlx::Error::RowNotFound => Error::RecordNotFound,
+                _ => Error::DatabaseQuery(e),
+            }
+        })?;
+
+        Ok(key)
+    }
+}
@@ -0,0 +1,68 @@
+use super::Store;
+
+use sqlx::{postgres::PgRow, Row};
+use secrecy::Secret;
+
+use crate::locale::Locale;
+use crate::user::User;
+use crate::user_pass::UserPass;
+use crate::Error;
+
+impl Store {
+    pub async fn get_user_by_identity(&self, provider: &str, subject: &str) -> Result<User, Error> {
+        let user = sqlx::query(
+            r#"
+                 select users.id, users.created_at, name, email::TEXT, password_hash, activated, activated_at, locale, version
+                 from users
+                 inner join user_identities on user_identities.user_id = users.id
+                 where user_identities.provider = $1 and user_identities.subject = $2
+            "#
+        )
+        .bind(provider)
+        .bind(subject)
+        .map(|row: PgRow| User {
+            id: row.get("id"),
+            created_at: row.get("created_at"),
+            name: row.get("name"),
+            email: row.get("email"),
+            password: UserPass(Secret::new(String::default())),
+            password_hash: Secret::new(row.get("password_hash")),
+            activated: row.get("activated"),
+            activated_at: row.get("activated_at"),
+            locale: Locale::parse(row.get("locale")).unwrap_or_default(),
+            version: row.get("version"),
+        })
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            match e {
+                sqlx::Error::RowNotFound => Error::RecordNotFound,
+                _ => Error::DatabaseQuery(e),
+            }
+        })?;
+
+        Ok(user)
+    }
+
+    pub async fn link_identity(&self, user_id: i64, provider: &str, subject: &str) -> Result<(), Error> {
+        sqlx::query!(
+            r#"
+               insert into user_identities (provider, subject, user_id)
+               values ($1, $2, $3)
+               on conflict (provider, subject) do nothing
+            "#,
+            provider,
+            subject,
+            user_id,
+        )
+        .execute(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+use super::Store;
+
+use chrono::Utc;
+
+use crate::invitation::Invitation;
+use crate::Error;
+
+impl Store {
+    pub async fn add_invitation(&self, inv: &mut Invitation) -> Result<(), Error> {
+        let ret = sqlx::query!(
+            r#"
+               insert into invitations (hash, email, permissions, expiry, invited_by, created_at)
+               values ($1, $2::TEXT::CITEXT, $3, $4, $5, $6)
+               returning id
+            "#,
+            inv.hash,
+            inv.email,
+            &inv.permissions,
+            inv.expiry,
+            inv.invited_by,
+            inv.created_at,
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?;
+
+        inv.id = ret.id;
+        Ok(())
+    }
+
+    pub async fn get_invitation(&self, hash: &[u8]) -> Result<Invitation, Error> {
+        let inv = sqlx::query_as!(
+            Invitation,
+            r#"
+               select id, ''::text as "plain_text!", hash, email::text as "email!", permissions,
+                      expiry, invited_by, created_at
+               from invitations
+               where hash = $1 and expiry > $2
+            "#,
+            hash,
+            Utc::now(),
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            match e {
+                sqlx::Error::RowNotFound => Error::RecordNotFound,
+                _ => Error::DatabaseQuery(e),
+            }
+        })?;
+
+        Ok(inv)
+    }
+
+    pub async fn list_invitations(&self) -> Result<Vec<Invitation>, Error> {
+        let invs = sqlx::query_as!(
+            Invitation,
+            r#"
+               select id, ''::text as "plain_text!", hash, email::text as "email!", permissions,
+                      expiry, invited_by, created_at
+               from invitations
+               where expiry > $1
+               order by id
+            "#,
+            Utc::now(),
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?;
+
+        Ok(invs)
+    }
+
+    pub async fn delete_invitation(&self, id: i64) -> Result<u64, Error> {
+        let remove_count = sqlx::query!(
+            r#"
+               delete from invitations where id = $1
+            "#,
+            id,
+        )
+        .execute(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?
+        .rows_affected();
+
+        Ok(remove_count)
+    }
+
+    pub async fn purge_expired_invitations(&self, limit: i64) -> Result<u64, Error> {
+        let remove_count = sqlx::query!(
+            r#"
+               delete from invitations
+               where id in (
+                   select id from invitations where expiry <= $1 limit $2
+               )
+            "#,
+            Utc::now(),
+            limit,
+        )
+        .execute(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?
+        .rows_affected();
+
+        Ok(remove_count)
+    }
+}
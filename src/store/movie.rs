@@ -13,8 +13,8 @@ impl Store {
     pub async fn add_movie(&self, movie: &mut Movie) -> Result<(), Error> {
         match sqlx::query(
             r#"
-                insert into movies (title, year, runtime, genres) 
-                values ($1, $2, $3, $4)
+                insert into movies (title, year, runtime, genres, created_by) 
+                values ($1, $2, $3, $4, $5)
                 returning id, created_at, version
             "#,
         )
@@ -22,6 +22,7 @@ impl Store {
         .bind(movie.year)
         .bind(movie.runtime)
         .bind(&movie.genres)
+        .bind(movie.created_by)
         .map(|row: PgRow| {
             movie.id = row.get("id");
             movie.created_at = row.get("created_at");
@@ -42,7 +43,7 @@ impl Store {
         let row = sqlx::query_as!(
             Movie,
             r#"
-                        select id, created_at, title, year, runtime, genres, version
+                        select id, created_at, title, year, runtime, genres, version, created_by
                         from movies
                         where id = $1
                     "#,
@@ -115,26 +116,35 @@ impl Store {
         &self,
         title: &str,
         genres: Vec<&str>,
+        created_by: Option<i64>,
         filter: &Filter<'_>,
+        paged: bool,
     ) -> Result<(MetaData, Vec<Movie>), Error> {
+        // unpaged, every match is returned in the filter's order
+        let (limit, offset) = match paged {
+            true => (Some(filter.limit()), filter.offset()),
+            false => (None, 0),
+        };
         let mut count = 0i64;
         match sqlx::query(
             &format!(
             r#"
-                select count(*) over(), id, created_at, title, year, runtime, genres, version
+                select count(*) over(), id, created_at, title, year, runtime, genres, version, created_by
                 from movies
                 where (to_tsvector('simple', title) @@ plainto_tsquery('simple', $1) or $1 = '') 
                 and (genres @> $2 or $2 = '{{}}')     
+                and (created_by = $3 or $3 is null)
                 order by {} {}, id asc
-                limit $3 offset $4      
+                limit $4 offset $5      
             "#,
             filter.sort_column().unwrap(), filter.sort_direction(),
             )
         )
         .bind(title)
         .bind(genres)
-        .bind(filter.limit())
-        .bind(filter.offset())
+        .bind(created_by)
+        .bind(limit)
+        .bind(offset)
         .map(|row: PgRow| {
             count = row.get(0);
             Movie {
@@ -145,6 +155,7 @@ impl Store {
                 runtime: row.get("runtime"),
                 genres: row.get("genres"),
                 version: row.get("version"),
+                created_by: row.get("created_by"),
             }
         })
         .fetch_all(&self.db)
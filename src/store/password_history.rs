@@ -0,0 +1,101 @@
+use super::Store;
+
+use secrecy::{ExposeSecret, Secret};
+
+use crate::user::User;
+use crate::Error;
+
+impl Store {
+    // most recent first
+    pub async fn password_history(&self, user_id: i64, limit: i64) -> Result<Vec<Secret<String>>, Error> {
+        let hashes = sqlx::query_scalar!(
+            r#"
+               select password_hash from password_history
+               where user_id = $1
+               order by id desc
+               limit $2
+            "#,
+            user_id,
+            limit,
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?;
+
+        Ok(hashes.into_iter().map(Secret::new).collect())
+    }
+
+    // swaps the password and records the replaced hash in one transaction, keeping
+    // only the `keep` newest ones, nothing is recorded when keep is 0
+    pub async fn update_password(&self, user: &mut User, password_hash: Secret<String>, keep: i64) -> Result<(), Error> {
+        let mut tx = self.db.begin().await.map_err(Error::DatabaseQuery)?;
+
+        let version = sqlx::query_scalar!(
+            r#"
+               update users
+               set password_hash = $1, version = version + 1
+               where id = $2 and version = $3
+               returning version
+            "#,
+            password_hash.expose_secret(),
+            user.id,
+            user.version,
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            match e {
+                sqlx::Error::RowNotFound => Error::EditConflict,
+                _ => Error::DatabaseQuery(e),
+            }
+        })?;
+
+        if keep > 0 {
+            sqlx::query!(
+                r#"
+                   insert into password_history (user_id, password_hash)
+                   values ($1, $2)
+                "#,
+                user.id,
+                user.password_hash.expose_secret(),
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                tracing::error!("{:?}", e);
+                Error::DatabaseQuery(e)
+            })?;
+
+            sqlx::query!(
+                r#"
+                   delete from password_history
+                   where user_id = $1 and id not in (
+                       select id from password_history
+                       where user_id = $1
+                       order by id desc
+                       limit $2
+                   )
+                "#,
+                user.id,
+                keep,
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                tracing::error!("{:?}", e);
+                Error::DatabaseQuery(e)
+            })?;
+        }
+
+        tx.commit().await.map_err(Error::DatabaseQuery)?;
+        self.auth_cache.invalidate_user(user.id).await;
+
+        user.password_hash = password_hash;
+        user.version = version;
+        Ok(())
+    }
+}
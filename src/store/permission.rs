@@ -1,19 +1,37 @@
 use super::Store;
 
+use chrono::{DateTime, Utc};
+
+use crate::role::Grant;
 use crate::Error;
 
 impl Store {
 
+    // unexpired direct grants plus whatever the user's roles grant, role wildcards
+    // are resolved against the known codes the same way as `role::grants`
     pub async fn permissions_by_user(&self, user_id: i64) -> Result<Vec<String>, Error> {
           let row = sqlx::query!(
             r#"
                 select permissions.code
                 from permissions
-                inner join users_permissions on users_permissions.permission_id = permissions.id
-                inner join users on users_permissions.user_id = users.id
-                where users.id = $1
+                where exists (
+                    select 1 from users_permissions
+                    where users_permissions.permission_id = permissions.id
+                    and users_permissions.user_id = $1
+                    and (users_permissions.expires_at is null or users_permissions.expires_at > $2)
+                )
+                or exists (
+                    select 1 from users_roles
+                    inner join roles on roles.id = users_roles.role_id
+                    cross join unnest(roles.permissions) as pattern
+                    where users_roles.user_id = $1
+                    and (pattern = permissions.code or pattern = '*'
+                         or (pattern like '%:*' and starts_with(permissions.code, left(pattern, -1))))
+                )
+                order by permissions.code
             "#,
               user_id,
+              Utc::now(),
           )
           .map(|ret|{ 
               ret.code
@@ -28,13 +46,39 @@ impl Store {
           Ok(row)
     }
 
-    pub async fn grant_permissions_to_user(&self, user_id: i64, codes: &Vec<String>) -> Result<(), Error> {
+    pub async fn all_permissions(&self) -> Result<Vec<String>, Error> {
+        let codes = sqlx::query!(
+            r#"
+                select code from permissions order by code
+            "#,
+        )
+        .map(|ret| ret.code)
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?;
+
+        Ok(codes)
+    }
+
+    // granting an already held code replaces its expiry
+    pub async fn grant_permissions_to_user(
+        &self,
+        user_id: i64,
+        codes: &Vec<String>,
+        expires_at: Option<DateTime<Utc>>,
+        granted_by: Option<i64>,
+    ) -> Result<(), Error> {
          let _count =  sqlx::query!(
              r#"
-              insert into users_permissions
-              select $1, permissions.id from permissions where permissions.code = ANY($2)
+              insert into users_permissions (user_id, permission_id, expires_at, granted_by)
+              select $1, permissions.id, $3, $4 from permissions where permissions.code = ANY($2)
+              on conflict (user_id, permission_id)
+              do update set expires_at = excluded.expires_at, granted_by = excluded.granted_by
              "#,
-             user_id, codes,
+             user_id, codes, expires_at, granted_by,
          )
         .execute(&self.db)
         .await
@@ -46,7 +90,88 @@ impl Store {
 
         println!("grant count = {}", _count);
 
+        self.auth_cache.invalidate_user(user_id).await;
+
         Ok(())
     }
 
+    pub async fn revoke_permission_from_user(&self, user_id: i64, code: &str) -> Result<u64, Error> {
+        let remove_count = sqlx::query!(
+            r#"
+              delete from users_permissions
+              using permissions
+              where users_permissions.permission_id = permissions.id
+              and users_permissions.user_id = $1 and permissions.code = $2
+            "#,
+            user_id,
+            code,
+        )
+        .execute(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?
+        .rows_affected();
+
+        if remove_count > 0 {
+            self.auth_cache.invalidate_user(user_id).await;
+        }
+
+        Ok(remove_count)
+    }
+
+    pub async fn grants_by_user(&self, user_id: i64) -> Result<Vec<Grant>, Error> {
+        let grants = sqlx::query_as!(
+            Grant,
+            r#"
+              select permissions.code, users_permissions.expires_at, users_permissions.granted_by
+              from users_permissions
+              inner join permissions on permissions.id = users_permissions.permission_id
+              where users_permissions.user_id = $1
+              order by permissions.code
+            "#,
+            user_id,
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?;
+
+        Ok(grants)
+    }
+
+    pub async fn purge_expired_grants(&self, limit: i64) -> Result<u64, Error> {
+        let mut user_ids = sqlx::query_scalar!(
+            r#"
+              delete from users_permissions
+              where (user_id, permission_id) in (
+                  select user_id, permission_id from users_permissions
+                  where expires_at <= $1
+                  limit $2
+              )
+              returning user_id
+            "#,
+            Utc::now(),
+            limit,
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?;
+        let remove_count = user_ids.len() as u64;
+
+        user_ids.sort_unstable();
+        user_ids.dedup();
+        for user_id in user_ids {
+            self.auth_cache.invalidate_user(user_id).await;
+        }
+
+        Ok(remove_count)
+    }
+
 }
@@ -0,0 +1,147 @@
+use super::Store;
+
+use crate::role::Role;
+use crate::Error;
+
+impl Store {
+    pub async fn add_role(&self, name: &str, permissions: &[String]) -> Result<Role, Error> {
+        match sqlx::query_as!(
+            Role,
+            r#"
+               insert into roles (name, permissions)
+               values ($1, $2)
+               returning id, created_at, name, permissions
+            "#,
+            name,
+            permissions,
+        )
+        .fetch_one(&self.db)
+        .await
+        {
+            Ok(role) => Ok(role),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                match e {
+                    sqlx::Error::Database(ref de) if de.is_unique_violation() => Err(Error::DuplicateName),
+                    _ => Err(Error::DatabaseQuery(e)),
+                }
+            }
+        }
+    }
+
+    pub async fn list_roles(&self) -> Result<Vec<Role>, Error> {
+        let roles = sqlx::query_as!(
+            Role,
+            r#"
+               select id, created_at, name, permissions
+               from roles
+               order by id
+            "#,
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?;
+
+        Ok(roles)
+    }
+
+    pub async fn get_role(&self, name: &str) -> Result<Role, Error> {
+        let role = sqlx::query_as!(
+            Role,
+            r#"
+               select id, created_at, name, permissions
+               from roles
+               where name = $1
+            "#,
+            name,
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            match e {
+                sqlx::Error::RowNotFound => Error::RecordNotFound,
+                _ => Error::DatabaseQuery(e),
+            }
+        })?;
+
+        Ok(role)
+    }
+
+    pub async fn roles_by_user(&self, user_id: i64) -> Result<Vec<String>, Error> {
+        let names = sqlx::query!(
+            r#"
+               select roles.name
+               from roles
+               inner join users_roles on users_roles.role_id = roles.id
+               where users_roles.user_id = $1
+               order by roles.name
+            "#,
+            user_id,
+        )
+        .map(|ret| ret.name)
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?;
+
+        Ok(names)
+    }
+
+    // returns 0 when the role does not exist or is already assigned
+    pub async fn assign_role(&self, user_id: i64, name: &str) -> Result<u64, Error> {
+        let count = sqlx::query!(
+            r#"
+               insert into users_roles
+               select $1, roles.id from roles where roles.name = $2
+               on conflict do nothing
+            "#,
+            user_id,
+            name,
+        )
+        .execute(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?
+        .rows_affected();
+
+        if count > 0 {
+            self.auth_cache.invalidate_user(user_id).await;
+        }
+
+        Ok(count)
+    }
+
+    pub async fn unassign_role(&self, user_id: i64, name: &str) -> Result<u64, Error> {
+        let remove_count = sqlx::query!(
+            r#"
+               delete from users_roles
+               using roles
+               where users_roles.role_id = roles.id
+               and users_roles.user_id = $1 and roles.name = $2
+            "#,
+            user_id,
+            name,
+        )
+        .execute(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?
+        .rows_affected();
+
+        if remove_count > 0 {
+            self.auth_cache.invalidate_user(user_id).await;
+        }
+
+        Ok(remove_count)
+    }
+}
@@ -1,19 +1,28 @@
 use super::Store;
 
-use crate::token::Token;
+use chrono::Utc;
+
+use crate::session::{ClientInfo, Session};
+use crate::token::{Token, TokenAuth, SCOPE_AUTHENTICATION, SCOPE_REFRESH};
 use crate::Error;
 
 impl Store {
     pub async fn save_token(&self, token: &Token) -> Result<(), Error> {
         sqlx::query!(
             r#"
-              insert into tokens (hash, user_id, expiry, scope) 
-              values ($1, $2, $3, $4)   
+              insert into tokens (hash, user_id, expiry, scope, created_at, last_used_at, ip, user_agent, family, impersonator_id) 
+              values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)   
              "#,
             token.hash,
             token.user_id,
             token.expiry,
             token.scope,
+            token.created_at,
+            token.last_used_at,
+            token.ip,
+            token.user_agent,
+            token.family,
+            token.impersonator_id,
         )
         .execute(&self.db)
         .await
@@ -40,6 +49,210 @@ impl Store {
             Error::DatabaseQuery(e)
         })?;
 
+        self.auth_cache.invalidate_user(user_id).await;
+
         Ok(())
     }
+
+    // resolves an access token to its user and everything the user is granted in one
+    // round trip, touching the token on the way. the permission codes match `permissions_by_user`
+    pub async fn authenticate_token(&self, hash: &[u8], client: &ClientInfo) -> Result<TokenAuth, Error> {
+        let now = Utc::now();
+        let auth = sqlx::query_as!(
+            TokenAuth,
+            r#"
+               with t as (
+                   update tokens set last_used_at = $3, ip = $4, user_agent = $5
+                   where hash = $1 and scope = $2 and expiry > $3
+                   returning user_id, expiry, impersonator_id
+               )
+               select users.id as user_id, users.activated, t.expiry, t.impersonator_id,
+                   (select coalesce(array_agg(permissions.code order by permissions.code), '{}')
+                    from permissions
+                    where exists (
+                        select 1 from users_permissions
+                        where users_permissions.permission_id = permissions.id
+                        and users_permissions.user_id = users.id
+                        and (users_permissions.expires_at is null or users_permissions.expires_at > $3)
+                    )
+                    or exists (
+                        select 1 from users_roles
+                        inner join roles on roles.id = users_roles.role_id
+                        cross join unnest(roles.permissions) as pattern
+                        where users_roles.user_id = users.id
+                        and (pattern = permissions.code or pattern = '*'
+                             or (pattern like '%:*' and starts_with(permissions.code, left(pattern, -1))))
+                    )) as "permissions!",
+                   (select coalesce(array_agg(roles.name order by roles.name), '{}')
+                    from roles
+                    inner join users_roles on users_roles.role_id = roles.id
+                    where users_roles.user_id = users.id) as "roles!"
+               from t
+               inner join users on users.id = t.user_id
+            "#,
+            hash,
+            SCOPE_AUTHENTICATION,
+            now,
+            client.ip,
+            client.user_agent,
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            match e {
+                sqlx::Error::RowNotFound => Error::RecordNotFound,
+                _ => Error::DatabaseQuery(e),
+            }
+        })?;
+
+        Ok(auth)
+    }
+
+    pub async fn sessions_by_user(
+        &self,
+        user_id: i64,
+        scope: &str,
+        current_hash: Option<&[u8]>,
+    ) -> Result<Vec<Session>, Error> {
+        let sessions = sqlx::query!(
+            r#"
+               select hash, created_at, last_used_at, expiry, ip, user_agent, impersonator_id
+               from tokens
+               where user_id = $1 and scope = $2 and expiry > $3 and not consumed
+               order by coalesce(last_used_at, created_at) desc
+            "#,
+            user_id,
+            scope,
+            Utc::now(),
+        )
+        .map(|row| Session {
+            id: Session::id_of(&row.hash),
+            current: Some(row.hash.as_slice()) == current_hash,
+            created_at: row.created_at,
+            last_used_at: row.last_used_at,
+            expiry: row.expiry,
+            ip: row.ip,
+            user_agent: row.user_agent,
+            impersonator_id: row.impersonator_id,
+        })
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?;
+
+        Ok(sessions)
+    }
+
+    pub async fn delete_session(&self, user_id: i64, scope: &str, id_prefix: &[u8]) -> Result<u64, Error> {
+        let remove_count = sqlx::query!(
+            r#"
+               delete from tokens t
+               using tokens s
+               where s.user_id = $1 and s.scope = $2 
+               and substring(s.hash from 1 for length($3::bytea)) = $3::bytea
+               and t.user_id = $1 and (t.hash = s.hash or t.family = s.family)
+            "#,
+            user_id,
+            scope,
+            id_prefix,
+        )
+        .execute(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?
+        .rows_affected();
+
+        if remove_count > 0 {
+            self.auth_cache.invalidate_user(user_id).await;
+        }
+
+        Ok(remove_count)
+    }
+
+    // marks a refresh token as used and reports the family it belongs to,
+    // together with whether it had already been used before this call
+    pub async fn consume_refresh_token(&self, hash: &[u8]) -> Result<(Vec<u8>, bool), Error> {
+        let ret = sqlx::query!(
+            r#"
+               with old as (
+                   select hash, family, consumed from tokens
+                   where hash = $1 and scope = $2 and expiry > $3
+                   for update
+               )
+               update tokens set consumed = true
+               from old
+               where tokens.hash = old.hash
+               returning old.family, old.consumed
+            "#,
+            hash,
+            SCOPE_REFRESH,
+            Utc::now(),
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            match e {
+                sqlx::Error::RowNotFound => Error::RecordNotFound,
+                _ => Error::DatabaseQuery(e),
+            }
+        })?;
+
+        match ret.family {
+            Some(family) => Ok((family, ret.consumed)),
+            None => Err(Error::RecordNotFound),
+        }
+    }
+
+    pub async fn delete_token_family(&self, family: &[u8], scope: Option<&str>) -> Result<u64, Error> {
+        let user_ids = sqlx::query_scalar!(
+            r#"
+               delete from tokens where family = $1 and (scope = $2 or $2 is null)
+               returning user_id
+            "#,
+            family,
+            scope,
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?;
+
+        // a family belongs to a single user
+        if let Some(&user_id) = user_ids.first() {
+            self.auth_cache.invalidate_user(user_id).await;
+        }
+
+        Ok(user_ids.len() as u64)
+    }
+
+    // deletes at most `limit` expired tokens so a large backlog never holds locks for long
+    pub async fn purge_expired_tokens(&self, limit: i64) -> Result<u64, Error> {
+        let remove_count = sqlx::query!(
+            r#"
+               delete from tokens
+               where hash in (
+                   select hash from tokens where expiry <= $1 limit $2
+               )
+            "#,
+            Utc::now(),
+            limit,
+        )
+        .execute(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?
+        .rows_affected();
+
+        Ok(remove_count)
+    }
 }
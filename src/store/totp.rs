@@ -0,0 +1,149 @@
+use super::Store;
+
+use secrecy::{ExposeSecret, Secret};
+
+use crate::Error;
+
+pub struct TotpRecord {
+    pub secret: Vec<u8>,
+    pub enabled: bool,
+}
+
+impl Store {
+    // starts a fresh enrolment, any previous pending secret and recovery codes are replaced
+    pub async fn save_totp_enrolment(
+        &self,
+        user_id: i64,
+        secret: &[u8],
+        code_hashes: &[Secret<String>],
+    ) -> Result<(), Error> {
+        let hashes: Vec<String> = code_hashes.iter().map(|h| h.expose_secret().clone()).collect();
+        let mut tx = self.db.begin().await.map_err(Error::DatabaseQuery)?;
+
+        sqlx::query!(
+            r#"
+               insert into totp_secrets (user_id, secret, enabled)
+               values ($1, $2, false)
+               on conflict (user_id) do update set secret = $2, enabled = false, last_step = null, created_at = now()
+            "#,
+            user_id,
+            secret,
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?;
+
+        sqlx::query!(
+            r#"
+               delete from recovery_codes where user_id = $1
+            "#,
+            user_id,
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?;
+
+        sqlx::query!(
+            r#"
+               insert into recovery_codes (user_id, code_hash)
+               select $1, unnest($2::text[])
+            "#,
+            user_id,
+            &hashes,
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?;
+
+        tx.commit().await.map_err(Error::DatabaseQuery)?;
+        Ok(())
+    }
+
+    pub async fn get_totp(&self, user_id: i64) -> Result<TotpRecord, Error> {
+        let record = sqlx::query_as!(
+            TotpRecord,
+            r#"
+               select secret, enabled from totp_secrets where user_id = $1
+            "#,
+            user_id,
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            match e {
+                sqlx::Error::RowNotFound => Error::RecordNotFound,
+                _ => Error::DatabaseQuery(e),
+            }
+        })?;
+
+        Ok(record)
+    }
+
+    // records the time step of an accepted code and enables 2fa, returns false
+    // when the step (or a later one) was already used, which means a replay
+    pub async fn accept_totp_step(&self, user_id: i64, step: i64) -> Result<bool, Error> {
+        let count = sqlx::query!(
+            r#"
+               update totp_secrets set last_step = $2, enabled = true
+               where user_id = $1 and (last_step is null or last_step < $2)
+            "#,
+            user_id,
+            step,
+        )
+        .execute(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?
+        .rows_affected();
+
+        Ok(count == 1)
+    }
+
+    pub async fn unused_recovery_codes(&self, user_id: i64) -> Result<Vec<(i64, Secret<String>)>, Error> {
+        let codes = sqlx::query!(
+            r#"
+               select id, code_hash from recovery_codes where user_id = $1 and not used
+            "#,
+            user_id,
+        )
+        .map(|row| (row.id, Secret::new(row.code_hash)))
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?;
+
+        Ok(codes)
+    }
+
+    pub async fn use_recovery_code(&self, id: i64) -> Result<bool, Error> {
+        let count = sqlx::query!(
+            r#"
+               update recovery_codes set used = true where id = $1 and not used
+            "#,
+            id,
+        )
+        .execute(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?
+        .rows_affected();
+
+        Ok(count == 1)
+    }
+}
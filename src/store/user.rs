@@ -1,10 +1,12 @@
 use super::Store;
 
 use sqlx::{postgres::PgRow, Row};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use secrecy::{ExposeSecret, Secret};
 
+use crate::filter::{Filter, MetaData};
 use crate::token::Token;
+use crate::locale::Locale;
 use crate::user::User;
 use crate::user_pass::UserPass;
 use crate::Error;
@@ -14,19 +16,21 @@ impl Store {
     pub async fn add_user(&self, user: &mut User) -> Result<(), Error> {
         match sqlx::query!(
             r#"
-               insert into users (name, email, password_hash, activated) 
-               values ($1, $2::TEXT::CITEXT, $3, $4)
-               returning id, created_at, version
+               insert into users (name, email, password_hash, activated, activated_at, locale)
+               values ($1, $2::TEXT::CITEXT, $3, $4, case when $4 then now() end, $5)
+               returning id, created_at, version, activated_at
             "#,
             user.name.as_ref(),
             user.email.as_ref(),
             user.password_hash.expose_secret(),
             user.activated,
+            user.locale.as_str(),
         )
         .map(|ret| {
             user.version = ret.version;
             user.id = ret.id;
             user.created_at = ret.created_at;
+            user.activated_at = ret.activated_at;
         })
         .fetch_one(&self.db)
         .await
@@ -55,7 +59,7 @@ impl Store {
         let user = sqlx::query(
             r#"
             select users.id, users.created_at, users.name, users.email::text, 
-                   users.password_hash, users.activated, users.version
+                   users.password_hash, users.activated, users.activated_at, users.locale, users.version
             from users
             inner join tokens
             on users.id = tokens.user_id
@@ -75,6 +79,8 @@ impl Store {
             password: UserPass(Secret::new(String::default())),
             password_hash: Secret::new(row.get("password_hash")),
             activated: row.get("activated"),
+            activated_at: row.get("activated_at"),
+            locale: Locale::parse(row.get("locale")).unwrap_or_default(),
             version: row.get("version"),
         })
         .fetch_one(&self.db)
@@ -94,7 +100,7 @@ impl Store {
     pub async fn get_user_by_email(&self, email: impl AsRef<str>) -> Result<User, Error>  {
         let user = sqlx::query(
             r#"
-                 select id, created_at, name, email::TEXT, password_hash, activated, version
+                 select id, created_at, name, email::TEXT, password_hash, activated, activated_at, locale, version
                  from users
                  where email = $1::TEXT::CITEXT          
             "#
@@ -108,6 +114,42 @@ impl Store {
             password: UserPass(Secret::new(String::default())),
             password_hash: Secret::new(row.get("password_hash")),
             activated: row.get("activated"),
+            activated_at: row.get("activated_at"),
+            locale: Locale::parse(row.get("locale")).unwrap_or_default(),
+            version: row.get("version"),
+        })
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            match e {
+                sqlx::Error::RowNotFound => Error::RecordNotFound,
+                _ => Error::DatabaseQuery(e),
+            }
+        })?;
+
+        Ok(user)
+    }
+
+    pub async fn get_user(&self, id: i64) -> Result<User, Error>  {
+        let user = sqlx::query(
+            r#"
+                 select id, created_at, name, email::TEXT, password_hash, activated, activated_at, locale, version
+                 from users
+                 where id = $1
+            "#
+        )
+        .bind(id)
+        .map(|row: PgRow| User {
+            id: row.get("id"),
+            created_at: row.get("created_at"),
+            name: row.get("name"),
+            email: row.get("email"),
+            password: UserPass(Secret::new(String::default())),
+            password_hash: Secret::new(row.get("password_hash")),
+            activated: row.get("activated"),
+            activated_at: row.get("activated_at"),
+            locale: Locale::parse(row.get("locale")).unwrap_or_default(),
             version: row.get("version"),
         })
         .fetch_one(&self.db)
@@ -127,9 +169,10 @@ impl Store {
         match sqlx::query!(
             r#"
                update users 
-               set name = $1, email = $2::TEXT::CITEXT, password_hash = $3, activated = $4, version = version + 1
+               set name = $1, email = $2::TEXT::CITEXT, password_hash = $3, activated = $4, version = version + 1,
+                   activated_at = case when $4 then coalesce(activated_at, now()) else activated_at end
                where id = $5 and version = $6
-               returning version              
+               returning version, activated_at
             "#,
             user.name.as_ref(),
             user.email.as_ref(),
@@ -140,11 +183,15 @@ impl Store {
         )
         .map(|ret| {
             user.version = ret.version;
+            user.activated_at = ret.activated_at;
         })
         .fetch_one(&self.db)
         .await
         {
-            Ok(_) => Ok(()),
+            Ok(_) => {
+                self.auth_cache.invalidate_user(user.id).await;
+                Ok(())
+            }
             Err(e) => {
                 tracing::error!("{:?}", e);
                 match e {
@@ -162,4 +209,102 @@ impl Store {
         }
 
     }
+
+    pub async fn delete_user(&self, id: i64) -> Result<u64, Error> {
+        let remove_count = sqlx::query!(
+            r#"
+               delete from users
+               where id = $1
+            "#,
+            id,
+        )
+        .execute(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?
+        .rows_affected();
+
+        if remove_count > 0 {
+            self.auth_cache.invalidate_user(id).await;
+        }
+
+        Ok(remove_count)
+    }
+
+    // accounts that were activated once and switched off later keep their activated_at
+    pub async fn purge_never_activated_users(&self, created_before: DateTime<Utc>, limit: i64) -> Result<u64, Error> {
+        let remove_count = sqlx::query!(
+            r#"
+               delete from users
+               where id in (
+                   select id from users
+                   where activated_at is null and not activated and created_at < $1
+                   limit $2
+               )
+            "#,
+            created_before,
+            limit,
+        )
+        .execute(&self.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            Error::DatabaseQuery(e)
+        })?
+        .rows_affected();
+
+        Ok(remove_count)
+    }
+
+    pub async fn search_users(
+        &self,
+        query: &str,
+        activated: Option<bool>,
+        filter: &Filter<'_>,
+    ) -> Result<(MetaData, Vec<User>), Error> {
+        let mut count = 0i64;
+        match sqlx::query(
+            &format!(
+            r#"
+                select count(*) over(), id, created_at, name, email::TEXT, password_hash, activated, activated_at, locale, version
+                from users
+                where (name ilike '%' || $1 || '%' or email::TEXT ilike '%' || $1 || '%' or $1 = '')
+                and (activated = $2 or $2 is null)
+                order by {} {}, id asc
+                limit $3 offset $4
+            "#,
+            filter.sort_column().unwrap(), filter.sort_direction(),
+            )
+        )
+        .bind(query)
+        .bind(activated)
+        .bind(filter.limit())
+        .bind(filter.offset())
+        .map(|row: PgRow| {
+            count = row.get(0);
+            User {
+                id: row.get("id"),
+                created_at: row.get("created_at"),
+                name: row.get("name"),
+                email: row.get("email"),
+                password: UserPass(Secret::new(String::default())),
+                password_hash: Secret::new(row.get("password_hash")),
+                activated: row.get("activated"),
+                activated_at: row.get("activated_at"),
+                locale: Locale::parse(row.get("locale")).unwrap_or_default(),
+                version: row.get("version"),
+            }
+        })
+        .fetch_all(&self.db)
+        .await
+        {
+            Ok(users) => Ok((MetaData::calc(count, filter.page, filter.page_size), users)),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(Error::DatabaseQuery(e))
+            }
+        }
+    }
 }
@@ -0,0 +1,84 @@
+use std::future::Future;
+use std::time::Duration;
+
+use chrono::Utc;
+
+use crate::config::SweepConfig;
+use crate::store::Store;
+use crate::Error;
+
+// periodically deletes rows that have stopped meaning anything, the reads
+// already ignore them so a missed run only costs table space
+pub struct Sweeper {
+    store: Store,
+    interval: Duration,
+    batch_size: i64,
+    unactivated_ttl: Option<chrono::Duration>,
+}
+
+impl Sweeper {
+    pub fn new(store: Store, config: &SweepConfig) -> Self {
+        Self {
+            store,
+            interval: config.sweep_interval,
+            batch_size: config.sweep_batch_size.max(1).into(),
+            unactivated_ttl: Some(config.unactivated_account_ttl)
+                .filter(|ttl| !ttl.is_zero())
+                .and_then(|ttl| chrono::Duration::from_std(ttl).ok()),
+        }
+    }
+
+    async fn sweep(&self) {
+        let store = &self.store;
+
+        log_purge("expired tokens", self.in_batches(|limit| store.purge_expired_tokens(limit)).await);
+        log_purge("expired permission grants", self.in_batches(|limit| store.purge_expired_grants(limit)).await);
+        log_purge("expired invitations", self.in_batches(|limit| store.purge_expired_invitations(limit)).await);
+
+        if let Some(ttl) = self.unactivated_ttl {
+            let created_before = Utc::now() - ttl;
+            let purged = self
+                .in_batches(|limit| store.purge_never_activated_users(created_before, limit))
+                .await;
+            log_purge("never activated accounts", purged);
+        }
+    }
+
+    // keeps deleting until a batch comes back short, so every statement stays small
+    async fn in_batches<F, Fut>(&self, mut purge: F) -> Result<u64, Error>
+    where
+        F: FnMut(i64) -> Fut,
+        Fut: Future<Output = Result<u64, Error>>,
+    {
+        let mut total = 0;
+        loop {
+            let count = purge(self.batch_size).await?;
+            total += count;
+            if count < self.batch_size as u64 {
+                return Ok(total);
+            }
+        }
+    }
+}
+
+fn log_purge(what: &str, purged: Result<u64, Error>) {
+    match purged {
+        Ok(0) => {}
+        Ok(count) => tracing::info!(count, "purged {}", what),
+        Err(e) => tracing::error!(err = %e, "failed to purge {}", what),
+    }
+}
+
+pub async fn run_sweeper(sweeper: Sweeper) -> Result<(), anyhow::Error> {
+    let mut ticker = tokio::time::interval(sweeper.interval.max(Duration::from_secs(1)));
+    loop {
+        ticker.tick().await;
+        sweeper.sweep().await;
+
+        // piggybacks on the ticker so cache effectiveness shows up at a steady rate
+        let (hits, misses) = sweeper.store.auth_cache.take_stats();
+        if hits + misses > 0 {
+            tracing::info!(hits, misses, "auth cache usage since last sweep");
+        }
+    }
+}